@@ -0,0 +1,91 @@
+#[path = "../src/runtime/mod.rs"]
+mod runtime;
+
+#[path = "../src/base/mod.rs"]
+mod base;
+
+#[path = "../src/ast.rs"]
+mod ast;
+
+use crate::ast::Instruction;
+use crate::runtime::mfm::{DenseGrid, EventWindow, Metadata};
+use crate::runtime::{Cursor, Runtime};
+use base::Symmetries;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+const SEED: u64 = 1337;
+const EVENTS: u64 = 10000;
+const PROGRAM_REPEAT: usize = 1000;
+
+/// Returns a runtime with Empty and a single radius 1 element of type 1 running `code`.
+fn runtime(code: Vec<Instruction<'static>>) -> Runtime<'static> {
+    let mut runtime = Runtime::new();
+    let mut elem = Metadata::new();
+    elem.type_num = 1;
+    elem.radius = 1;
+    runtime.type_map.insert(1, elem);
+    runtime.code_map.insert(1, code);
+    runtime
+}
+
+fn bench_diffusion(c: &mut Criterion) {
+    let runtime = runtime(vec![
+        Instruction::Push1,
+        Instruction::Push0,
+        Instruction::SwapSites,
+    ]);
+    let atom = runtime.type_map[&1].new_atom();
+
+    let mut group = c.benchmark_group("diffusion");
+    group.throughput(Throughput::Elements(EVENTS));
+    group.bench_function("dense_grid_64x64", |b| {
+        let mut rng = SmallRng::seed_from_u64(SEED);
+        let mut ew = DenseGrid::new(&mut rng, (64, 64)).unwrap();
+        for _ in 0..1000 {
+            ew.set(0, atom);
+            ew.reset();
+        }
+        let mut cursor = Cursor::new();
+        b.iter(|| {
+            for _ in 0..EVENTS {
+                Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map)
+                    .unwrap();
+                ew.reset();
+                cursor.reset(Symmetries::R000L);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let mut code = Vec::with_capacity(4 * PROGRAM_REPEAT);
+    for _ in 0..PROGRAM_REPEAT {
+        code.extend_from_slice(&[
+            Instruction::Push1,
+            Instruction::Push1,
+            Instruction::Add,
+            Instruction::Pop,
+        ]);
+    }
+    let runtime = runtime(code);
+
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(4 * PROGRAM_REPEAT as u64));
+    group.bench_function("linear", |b| {
+        let mut rng = SmallRng::seed_from_u64(SEED);
+        let mut ew = DenseGrid::new(&mut rng, (9, 9)).unwrap();
+        ew.set(0, runtime.type_map[&1].new_atom());
+        let mut cursor = Cursor::new();
+        b.iter(|| {
+            Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+            cursor.reset(Symmetries::R000L);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_diffusion, bench_dispatch);
+criterion_main!(benches);
@@ -6,7 +6,7 @@
 .author "Alan Zaffetti"
 .license "GPL-2.0-or-later"
 .symmetries NONE
-.radius 1
+.radius 2
 .field paint, 0,32
   .field r,   24,8
   .field g,   16,8
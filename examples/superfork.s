@@ -6,7 +6,7 @@
 .author "Alan Zaffetti"
 .license "GPL-2.0-or-later"
 .symmetries NONE
-.radius 1
+.radius 4
 
     push40           ; [i:=40]
 loop:
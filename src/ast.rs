@@ -1,11 +1,98 @@
 use crate::base::arith::Const;
 use crate::base::{FieldSelector, Symmetries};
+use std::convert::TryFrom;
+use std::fmt;
 
 #[derive(Copy, Clone, Debug)]
 pub enum Node<'input> {
     Label(&'input str),
     Metadata(Metadata<'input>),
     Instruction(Instruction<'input>),
+    /// Pushes the number of sites within the element's radius, which the compiler resolves once
+    /// the element's header is known.
+    SiteCount,
+}
+
+/// Lowers a `foreachsymmetry` block into straight-line code.
+///
+/// The body is repeated once per symmetry in `s`, in ascending bit order from R000L to R270R, and
+/// each copy runs between a save and restore of the current symmetry. An empty set runs the body
+/// once under R000L.
+pub fn for_each_symmetry<'input>(s: Symmetries, body: Vec<Node<'input>>) -> Vec<Node<'input>> {
+    let s = if s.is_empty() { Symmetries::R000L } else { s };
+    let mut nodes = Vec::new();
+    for i in 0..8 {
+        let x = Symmetries::from(1u8 << i);
+        if !s.contains(x) {
+            continue;
+        }
+        nodes.push(Node::Instruction(Instruction::SaveSymmetries));
+        nodes.push(Node::Instruction(Instruction::UseSymmetries(x)));
+        nodes.extend_from_slice(&body);
+        nodes.push(Node::Instruction(Instruction::RestoreSymmetries));
+    }
+    nodes
+}
+
+/// Lowers a `foreachsite` block into a counted loop.
+///
+/// Each iteration pushes a site number, from 1 up to the last site within the element's radius,
+/// and runs the body, which must consume it. The loop counter stays beneath it on the stack. A
+/// `None` in `body` marks a `break`, which leaves the loop once the site has been consumed.
+pub fn for_each_site<'input>(body: Vec<Option<Node<'input>>>) -> Vec<Node<'input>> {
+    let push = |x: i32| {
+        Node::Instruction(Instruction::Push(if x < 0 {
+            x.into()
+        } else {
+            (x as u16).into()
+        }))
+    };
+    let jump = Node::Instruction(Instruction::JumpRelativeOffset);
+    let len: i32 = body
+        .iter()
+        .map(|n| match n {
+            Some(Node::Instruction(_)) => 1,
+            Some(_) => 0,
+            None => 2,
+        })
+        .sum();
+    // Offsets are taken relative to the jump which consumes them. The loop starts at 1, tests the
+    // counter at 6, runs the body from 8 and ends by dropping the counter.
+    let end = 8 + len + 4;
+    let mut nodes = vec![
+        Node::Instruction(Instruction::Push1),
+        Node::SiteCount,
+        Node::Instruction(Instruction::Over),
+        Node::Instruction(Instruction::LessEqual),
+        push(end - 6),
+        Node::Instruction(Instruction::Mul),
+        jump,
+        Node::Instruction(Instruction::Dup),
+    ];
+    let mut ip = 8;
+    for n in body {
+        match n {
+            Some(n) => {
+                if let Node::Instruction(_) = n {
+                    ip += 1;
+                }
+                nodes.push(n);
+            }
+            None => {
+                nodes.push(push(end - (ip + 1)));
+                nodes.push(jump);
+                ip += 2;
+            }
+        }
+    }
+    nodes.extend_from_slice(&[
+        Node::Instruction(Instruction::Push1),
+        Node::Instruction(Instruction::Add),
+        push(1 - (ip + 3)),
+        jump,
+        Node::Instruction(Instruction::Pop),
+    ]);
+    nodes
 }
 
 #[repr(u8)]
@@ -22,6 +109,9 @@ pub enum Metadata<'input> {
     Symmetries(Symmetries),
     Field(&'input str, FieldSelector),
     Parameter(&'input str, Const),
+    Wrapping(bool),
+    Extends(&'input str),
+    Include(&'input str),
 }
 
 impl From<Metadata<'_>> for u8 {
@@ -38,11 +128,14 @@ impl From<Metadata<'_>> for u8 {
             Metadata::Symmetries(_) => 8,
             Metadata::Field(_, _) => 9,
             Metadata::Parameter(_, _) => 10,
+            Metadata::Wrapping(_) => 11,
+            Metadata::Extends(_) => 12,
+            Metadata::Include(_) => 13,
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Hash)]
 pub enum Arg<T, U> {
     Ast(T),
     Runtime(U),
@@ -75,7 +168,7 @@ impl<T, U> Arg<T, U> {
 }
 
 #[repr(u8)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Hash)]
 pub enum Instruction<'input> {
     Nop,
     Exit,
@@ -168,6 +261,328 @@ pub enum Instruction<'input> {
     SetPaint,
     GetPaint,
     Rand,
+    NeighborCount(Arg<&'input str, u16>),
+    RandRange,
+    AtomEqual,
+    Compare,
+    SetSignedField(Arg<&'input str, FieldSelector>),
+    SetSignedSiteField(Arg<&'input str, FieldSelector>),
+    GetParameterIndex(Arg<&'input str, u16>),
+    SiteIs(Arg<&'input str, u16>),
+    IsEmpty,
+    Begin,
+    Commit,
+    Abort,
+    GetParameterOf(Arg<&'input str, u32>),
+    SiteToXY,
+    XYToSite,
+    ModEuclid,
+    GetSitePaint,
+    SetSitePaint,
+    CountType,
+}
+
+impl Instruction<'_> {
+    /// Returns the number of values the instruction pops off and then pushes onto the stack.
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Instruction::Nop | Instruction::Exit => (0, 0),
+            Instruction::SwapSites | Instruction::SetSite => (2, 0),
+            Instruction::SetField(_) => (2, 1),
+            Instruction::SetSiteField(_) => (2, 0),
+            Instruction::GetSite
+            | Instruction::GetField(_)
+            | Instruction::GetSiteField(_)
+            | Instruction::GetSignedField(_)
+            | Instruction::GetSignedSiteField(_) => (1, 1),
+            Instruction::GetType(_) | Instruction::GetParameter(_) => (0, 1),
+            Instruction::Scan => (1, 1),
+            Instruction::SaveSymmetries
+            | Instruction::UseSymmetries(_)
+            | Instruction::RestoreSymmetries => (0, 0),
+            Instruction::Push0
+            | Instruction::Push1
+            | Instruction::Push2
+            | Instruction::Push3
+            | Instruction::Push4
+            | Instruction::Push5
+            | Instruction::Push6
+            | Instruction::Push7
+            | Instruction::Push8
+            | Instruction::Push9
+            | Instruction::Push10
+            | Instruction::Push11
+            | Instruction::Push12
+            | Instruction::Push13
+            | Instruction::Push14
+            | Instruction::Push15
+            | Instruction::Push16
+            | Instruction::Push17
+            | Instruction::Push18
+            | Instruction::Push19
+            | Instruction::Push20
+            | Instruction::Push21
+            | Instruction::Push22
+            | Instruction::Push23
+            | Instruction::Push24
+            | Instruction::Push25
+            | Instruction::Push26
+            | Instruction::Push27
+            | Instruction::Push28
+            | Instruction::Push29
+            | Instruction::Push30
+            | Instruction::Push31
+            | Instruction::Push32
+            | Instruction::Push33
+            | Instruction::Push34
+            | Instruction::Push35
+            | Instruction::Push36
+            | Instruction::Push37
+            | Instruction::Push38
+            | Instruction::Push39
+            | Instruction::Push40
+            | Instruction::Push(_) => (0, 1),
+            Instruction::Pop => (1, 0),
+            Instruction::Dup => (1, 2),
+            Instruction::Over => (2, 3),
+            Instruction::Swap => (2, 2),
+            Instruction::Rot => (3, 3),
+            Instruction::Call(_) | Instruction::Ret => (0, 0),
+            Instruction::Checksum => (1, 1),
+            Instruction::Add
+            | Instruction::Sub
+            | Instruction::Mod
+            | Instruction::Mul
+            | Instruction::Div
+            | Instruction::Less
+            | Instruction::LessEqual
+            | Instruction::Or
+            | Instruction::And
+            | Instruction::Xor
+            | Instruction::Equal
+            | Instruction::LShift
+            | Instruction::RShift => (2, 1),
+            Instruction::Neg
+            | Instruction::BitCount
+            | Instruction::BitScanForward
+            | Instruction::BitScanReverse => (1, 1),
+            Instruction::Jump(_) => (0, 0),
+            Instruction::JumpRelativeOffset
+            | Instruction::JumpZero(_)
+            | Instruction::JumpNonZero(_) => (1, 0),
+            Instruction::SetPaint => (1, 0),
+            Instruction::GetPaint | Instruction::Rand => (0, 1),
+            Instruction::NeighborCount(_) => (0, 1),
+            Instruction::RandRange => (1, 1),
+            Instruction::AtomEqual => (2, 1),
+            Instruction::Compare => (2, 1),
+            Instruction::SetSignedField(_) => (2, 1),
+            Instruction::SetSignedSiteField(_) => (2, 0),
+            Instruction::GetParameterIndex(_) => (1, 1),
+            Instruction::SiteIs(_) => (1, 1),
+            Instruction::IsEmpty => (1, 1),
+            Instruction::Begin | Instruction::Commit | Instruction::Abort => (0, 0),
+            Instruction::GetParameterOf(_) => (1, 1),
+            Instruction::SiteToXY => (1, 2),
+            Instruction::XYToSite => (2, 1),
+            Instruction::ModEuclid => (2, 1),
+            Instruction::GetSitePaint => (1, 1),
+            Instruction::SetSitePaint => (2, 0),
+            Instruction::CountType => (1, 1),
+        }
+    }
+}
+
+/// Where `check_stack_depth` found the stack going wrong: an instruction and the depth it is
+/// reached with. An `ip` one past the end of the code is the end of the code.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StackFault {
+    pub ip: usize,
+    pub depth: usize,
+}
+
+/// Follows every path through `code` from its start, tracking the stack depth with
+/// `Instruction::stack_effect`. `target` gives the code index the jump or call at `ip` goes to.
+///
+/// Fails where an instruction can be reached with fewer values on the stack than it pops. Where
+/// paths meet, the deeper of their depths is kept unless `balanced` is set, so that only code
+/// which underflows however it got there fails. With `balanced` set paths must instead meet at
+/// the same depth, and the stack must be empty at `exit` and at the end of the code.
+///
+/// The depth after a `call` returns is unknown and is not checked, nor, with `balanced` set, is the
+/// depth within a subroutine. Depths past `MAX_DEPTH` are also unknown. A `jumprelativeoffset`
+/// is followed where `relative_targets` works out where it goes, as for every `foreachsite`
+/// block, and otherwise fails the check wherever it is reached.
+pub fn check_stack_depth(
+    code: &[Instruction<'_>],
+    target: impl Fn(usize) -> Option<usize>,
+    balanced: bool,
+) -> Result<(), StackFault> {
+    const MAX_DEPTH: usize = 1024;
+    // Instructions reached other than by falling through from the one before, counting every
+    // offset a relative jump might take.
+    let unchecked = vec![false; code.len() + 1];
+    let mut entries = unchecked.clone();
+    entries[0] = true;
+    for ip in 0..code.len() {
+        let to = target(ip).into_iter();
+        for t in to.chain(relative_targets(code, ip, &unchecked).unwrap_or_default()) {
+            if t != ip + 1 {
+                entries[t.min(code.len())] = true;
+            }
+        }
+        // A call returns to the instruction after it.
+        if matches!(code[ip], Instruction::Call(_)) {
+            entries[ip + 1] = true;
+        }
+    }
+    let relative: Vec<Option<Vec<usize>>> = (0..code.len())
+        .map(|ip| relative_targets(code, ip, &entries))
+        .collect();
+    // `None` is an unknown depth, which is deeper than any known one.
+    let mut depths: Vec<Option<Option<usize>>> = vec![None; code.len()];
+    let mut fault = None;
+    let mut work = vec![(0usize, Some(0usize))];
+    while let Some((ip, d)) = work.pop() {
+        if ip >= code.len() || matches!(code[ip], Instruction::Exit) {
+            if let Some(depth) = d.filter(|depth| balanced && *depth != 0) {
+                fault = fault.or(Some(StackFault { ip, depth }));
+            }
+            if ip >= code.len() {
+                continue;
+            }
+        }
+        if let Some(old) = depths[ip] {
+            // When balanced a known depth is kept over an unknown one, since only it is checked.
+            let revisit = match (old, d) {
+                (Some(old), Some(depth)) if balanced && old != depth => {
+                    fault = fault.or(Some(StackFault { ip, depth }));
+                    false
+                }
+                (Some(old), Some(depth)) => depth > old,
+                (Some(_), None) => !balanced,
+                (None, Some(_)) => balanced,
+                (None, None) => false,
+            };
+            if !revisit {
+                continue;
+            }
+        }
+        depths[ip] = Some(d);
+        let (pops, pushes) = code[ip].stack_effect();
+        let next = d
+            .map(|depth| depth.saturating_sub(pops) + pushes)
+            .filter(|depth| *depth <= MAX_DEPTH);
+        let to = target(ip);
+        match code[ip] {
+            Instruction::Exit | Instruction::Ret => {}
+            Instruction::Jump(_) => work.extend(to.map(|t| (t, next))),
+            Instruction::JumpRelativeOffset => match &relative[ip] {
+                Some(targets) => work.extend(targets.iter().map(|&t| (t, next))),
+                None => {
+                    let depth = d.unwrap_or(MAX_DEPTH);
+                    fault = fault.or(Some(StackFault { ip, depth }));
+                }
+            },
+            Instruction::JumpZero(_) | Instruction::JumpNonZero(_) => {
+                work.extend(to.map(|t| (t, next)));
+                work.push((ip + 1, next));
+            }
+            Instruction::Call(_) => {
+                work.extend(to.map(|t| (t, if balanced { None } else { next })));
+                work.push((ip + 1, None));
+            }
+            _ => work.push((ip + 1, next)),
+        }
+    }
+    for (ip, d) in depths.iter().enumerate() {
+        if let Some(Some(depth)) = d {
+            if *depth < code[ip].stack_effect().0 {
+                return Err(StackFault { ip, depth: *depth });
+            }
+        }
+    }
+    fault.map_or(Ok(()), Err)
+}
+
+/// Returns the constant `i` pushes, if it is a push.
+fn pushed_const(i: Instruction<'_>) -> Option<Const> {
+    match i {
+        Instruction::Push(c) => Some(c),
+        // `push0` to `push40` have consecutive op codes.
+        _ => match u8::from(i) {
+            op @ 17..=57 => Some((op - 17).into()),
+            _ => None,
+        },
+    }
+}
+
+/// Returns the values the top of the stack may hold as instruction `ip` is reached, if they can
+/// be worked out from the instructions since the last of `entries` before it.
+///
+/// Only constants, comparisons and `add`, `sub` and `mul` of known values are followed, in
+/// either arithmetic mode. Anything else, or more than a few values, is unknown.
+fn stack_top_values(code: &[Instruction<'_>], ip: usize, entries: &[bool]) -> Option<Vec<Const>> {
+    const MAX_VALUES: usize = 8;
+    let mut start = ip;
+    while start > 0 && !entries[start] {
+        start -= 1;
+    }
+    // The values each slot pushed since `start` may hold, or `None` for any value.
+    let mut stack: Vec<Option<Vec<Const>>> = Vec::new();
+    for &i in &code[start..ip] {
+        let (pops, pushes) = i.stack_effect();
+        let args: Vec<_> = (0..pops).map(|_| stack.pop().flatten()).collect();
+        let values = match (i, args.as_slice()) {
+            (Instruction::Less | Instruction::LessEqual | Instruction::Equal, _) => {
+                Some(vec![Const::from(0), Const::from(1)])
+            }
+            (Instruction::Add | Instruction::Sub | Instruction::Mul, [Some(bs), Some(as_)]) => {
+                let mut v = Vec::new();
+                for &a in as_ {
+                    for &b in bs {
+                        v.extend_from_slice(&match i {
+                            Instruction::Add => [a + b, a.wrapping_add(b)],
+                            Instruction::Sub => [a - b, a.wrapping_sub(b)],
+                            _ => [a * b, a.wrapping_mul(b)],
+                        });
+                    }
+                }
+                Some(v).filter(|v| v.len() <= MAX_VALUES)
+            }
+            _ => pushed_const(i).map(|c| vec![c]),
+        };
+        if pushes > 0 {
+            stack.extend(std::iter::repeat(None).take(pushes - 1));
+            stack.push(values);
+        }
+    }
+    stack.pop().flatten()
+}
+
+/// Returns where the `jumprelativeoffset` at `ip` may go, as code indices where `code.len()` is
+/// the end of the code, or `None` if its offset is not known (see `stack_top_values`).
+fn relative_targets(code: &[Instruction<'_>], ip: usize, entries: &[bool]) -> Option<Vec<usize>> {
+    if !matches!(code.get(ip)?, Instruction::JumpRelativeOffset) {
+        return None;
+    }
+    let mut targets: Vec<usize> = stack_top_values(code, ip, entries)?
+        .into_iter()
+        .map(|c| {
+            let n = usize::try_from(u128::from(c.abs())).ok();
+            let t = if c.is_zero() {
+                Some(ip + 1)
+            } else if c.is_neg() {
+                n.and_then(|n| ip.checked_sub(n))
+            } else {
+                n.and_then(|n| ip.checked_add(n))
+            };
+            t.filter(|t| *t < code.len()).unwrap_or(code.len())
+        })
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    Some(targets)
 }
 
 impl From<Instruction<'_>> for u8 {
@@ -264,6 +679,184 @@ impl From<Instruction<'_>> for u8 {
             Instruction::SetPaint => 88,
             Instruction::GetPaint => 89,
             Instruction::Rand => 90,
+            Instruction::NeighborCount(_) => 91,
+            Instruction::RandRange => 92,
+            Instruction::AtomEqual => 93,
+            Instruction::Compare => 94,
+            Instruction::SetSignedField(_) => 95,
+            Instruction::SetSignedSiteField(_) => 96,
+            Instruction::GetParameterIndex(_) => 97,
+            Instruction::SiteIs(_) => 98,
+            Instruction::IsEmpty => 99,
+            Instruction::Begin => 100,
+            Instruction::Commit => 101,
+            Instruction::Abort => 102,
+            Instruction::GetParameterOf(_) => 103,
+            Instruction::SiteToXY => 104,
+            Instruction::XYToSite => 105,
+            Instruction::ModEuclid => 106,
+            Instruction::GetSitePaint => 107,
+            Instruction::SetSitePaint => 108,
+            Instruction::CountType => 109,
+        }
+    }
+}
+
+impl Instruction<'_> {
+    /// Returns the source mnemonic of the instruction.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Nop => "nop",
+            Instruction::Exit => "exit",
+            Instruction::SwapSites => "swapsites",
+            Instruction::SetSite => "setsite",
+            Instruction::SetField(_) => "setfield",
+            Instruction::SetSiteField(_) => "setsitefield",
+            Instruction::GetSite => "getsite",
+            Instruction::GetField(_) => "getfield",
+            Instruction::GetSiteField(_) => "getsitefield",
+            Instruction::GetSignedField(_) => "getsignedfield",
+            Instruction::GetSignedSiteField(_) => "getsignedsitefield",
+            Instruction::GetType(_) => "gettype",
+            Instruction::GetParameter(_) => "getparameter",
+            Instruction::Scan => "scan",
+            Instruction::SaveSymmetries => "savesymmetries",
+            Instruction::UseSymmetries(_) => "usesymmetries",
+            Instruction::RestoreSymmetries => "restoresymmetries",
+            Instruction::Push0 => "push0",
+            Instruction::Push1 => "push1",
+            Instruction::Push2 => "push2",
+            Instruction::Push3 => "push3",
+            Instruction::Push4 => "push4",
+            Instruction::Push5 => "push5",
+            Instruction::Push6 => "push6",
+            Instruction::Push7 => "push7",
+            Instruction::Push8 => "push8",
+            Instruction::Push9 => "push9",
+            Instruction::Push10 => "push10",
+            Instruction::Push11 => "push11",
+            Instruction::Push12 => "push12",
+            Instruction::Push13 => "push13",
+            Instruction::Push14 => "push14",
+            Instruction::Push15 => "push15",
+            Instruction::Push16 => "push16",
+            Instruction::Push17 => "push17",
+            Instruction::Push18 => "push18",
+            Instruction::Push19 => "push19",
+            Instruction::Push20 => "push20",
+            Instruction::Push21 => "push21",
+            Instruction::Push22 => "push22",
+            Instruction::Push23 => "push23",
+            Instruction::Push24 => "push24",
+            Instruction::Push25 => "push25",
+            Instruction::Push26 => "push26",
+            Instruction::Push27 => "push27",
+            Instruction::Push28 => "push28",
+            Instruction::Push29 => "push29",
+            Instruction::Push30 => "push30",
+            Instruction::Push31 => "push31",
+            Instruction::Push32 => "push32",
+            Instruction::Push33 => "push33",
+            Instruction::Push34 => "push34",
+            Instruction::Push35 => "push35",
+            Instruction::Push36 => "push36",
+            Instruction::Push37 => "push37",
+            Instruction::Push38 => "push38",
+            Instruction::Push39 => "push39",
+            Instruction::Push40 => "push40",
+            Instruction::Push(_) => "push",
+            Instruction::Pop => "pop",
+            Instruction::Dup => "dup",
+            Instruction::Over => "over",
+            Instruction::Swap => "swap",
+            Instruction::Rot => "rot",
+            Instruction::Call(_) => "call",
+            Instruction::Ret => "ret",
+            Instruction::Checksum => "checksum",
+            Instruction::Add => "add",
+            Instruction::Sub => "sub",
+            Instruction::Neg => "neg",
+            Instruction::Mod => "mod",
+            Instruction::Mul => "mul",
+            Instruction::Div => "div",
+            Instruction::Less => "less",
+            Instruction::LessEqual => "lessequal",
+            Instruction::Or => "or",
+            Instruction::And => "and",
+            Instruction::Xor => "xor",
+            Instruction::Equal => "equal",
+            Instruction::BitCount => "bitcount",
+            Instruction::BitScanForward => "bitscanforward",
+            Instruction::BitScanReverse => "bitscanreverse",
+            Instruction::LShift => "lshift",
+            Instruction::RShift => "rshift",
+            Instruction::Jump(_) => "jump",
+            Instruction::JumpRelativeOffset => "jumprelativeoffset",
+            Instruction::JumpZero(_) => "jumpzero",
+            Instruction::JumpNonZero(_) => "jumpnonzero",
+            Instruction::SetPaint => "setpaint",
+            Instruction::GetPaint => "getpaint",
+            Instruction::Rand => "rand",
+            Instruction::NeighborCount(_) => "neighborcount",
+            Instruction::RandRange => "randrange",
+            Instruction::AtomEqual => "atomeq",
+            Instruction::Compare => "cmp",
+            Instruction::SetSignedField(_) => "setsignedfield",
+            Instruction::SetSignedSiteField(_) => "setsignedsitefield",
+            Instruction::GetParameterIndex(_) => "getparamidx",
+            Instruction::SiteIs(_) => "siteis",
+            Instruction::IsEmpty => "isempty",
+            Instruction::Begin => "begin",
+            Instruction::Commit => "commit",
+            Instruction::Abort => "abort",
+            Instruction::GetParameterOf(_) => "getparamof",
+            Instruction::SiteToXY => "sitetoxy",
+            Instruction::XYToSite => "xytosite",
+            Instruction::ModEuclid => "modeuclid",
+            Instruction::GetSitePaint => "getsitepaint",
+            Instruction::SetSitePaint => "setsitepaint",
+            Instruction::CountType => "counttype",
+        }
+    }
+}
+
+impl<T: fmt::Display, U: fmt::Display> fmt::Display for Arg<T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ast(x) => x.fmt(f),
+            Self::Runtime(x) => x.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Instruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())?;
+        match self {
+            Instruction::SetField(x)
+            | Instruction::SetSiteField(x)
+            | Instruction::GetField(x)
+            | Instruction::GetSiteField(x)
+            | Instruction::GetSignedField(x)
+            | Instruction::GetSignedSiteField(x)
+            | Instruction::SetSignedField(x)
+            | Instruction::SetSignedSiteField(x) => write!(f, " {}", x),
+            Instruction::GetType(Arg::Ast(x))
+            | Instruction::NeighborCount(Arg::Ast(x))
+            | Instruction::SiteIs(Arg::Ast(x)) => write!(f, " \"{}\"", x),
+            Instruction::GetType(x) | Instruction::NeighborCount(x) | Instruction::SiteIs(x) => {
+                write!(f, " {}", x)
+            }
+            Instruction::GetParameter(x) => write!(f, " {}", x),
+            Instruction::UseSymmetries(x) => write!(f, " {}", x),
+            Instruction::Push(x) => write!(f, " {}", x),
+            Instruction::Call(x)
+            | Instruction::Jump(x)
+            | Instruction::JumpZero(x)
+            | Instruction::JumpNonZero(x)
+            | Instruction::GetParameterIndex(x) => write!(f, " {}", x),
+            Instruction::GetParameterOf(x) => write!(f, " {}", x),
+            _ => Ok(()),
         }
     }
 }
@@ -273,3 +866,49 @@ pub struct File<'input> {
     pub header: Vec<Node<'input>>,
     pub body: Vec<Node<'input>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::DataType;
+
+    #[test]
+    fn test_display() {
+        let field = FieldSelector {
+            offset: 80,
+            length: 16,
+            data_type: DataType::Unsigned,
+        };
+        let cases = [
+            (Instruction::Nop, "nop"),
+            (Instruction::Push5, "push5"),
+            (Instruction::Push(5u8.into()), "push 5"),
+            (Instruction::Push(Const::Signed(-5)), "push -5"),
+            (Instruction::Jump(Arg::Ast("loop")), "jump loop"),
+            (Instruction::Jump(Arg::Runtime(12)), "jump 12"),
+            (Instruction::SetField(Arg::Ast("count")), "setfield count"),
+            (
+                Instruction::SetField(Arg::Runtime(field)),
+                "setfield {80:16}",
+            ),
+            (Instruction::GetType(Arg::Ast("Res")), "gettype \"Res\""),
+            (Instruction::GetType(Arg::Runtime(2)), "gettype 2"),
+            (
+                Instruction::GetParameter(Arg::Runtime(Const::Signed(3))),
+                "getparameter +3",
+            ),
+            (
+                Instruction::UseSymmetries(Symmetries::R000L | Symmetries::R090R),
+                "usesymmetries R000L | R090R",
+            ),
+            (
+                Instruction::UseSymmetries(Symmetries::all()),
+                "usesymmetries ALL",
+            ),
+            (Instruction::UseSymmetries(0.into()), "usesymmetries NONE"),
+        ];
+        for (i, want) in cases.iter() {
+            assert_eq!(i.to_string(), *want);
+        }
+    }
+}
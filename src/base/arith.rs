@@ -1,5 +1,7 @@
-use crate::base::FieldSelector;
+use crate::base::{DataType, FieldSelector};
 use std::cmp::{Eq, Ordering};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::num::ParseIntError;
 use std::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Neg, Rem, Shl, Shr, Sub};
 
@@ -26,18 +28,24 @@ impl Const {
         }
     }
 
+    /// What `bitscanforward` and `bitscanreverse` return for zero, which has no set bit. Unlike
+    /// x86 `BSF` and `BSR`, whose result is then undefined, this is one past the last bit index.
+    pub const NO_BIT: u32 = BIT_SIZE as u32;
+
+    /// Returns the number of zero bits below the lowest set bit, or `NO_BIT` for zero.
     pub fn bitscanforward(&self) -> u32 {
-        match self {
-            Self::Unsigned(x) => x.trailing_zeros(),
-            Self::Signed(x) => x.trailing_zeros(),
+        if self.is_zero() {
+            return Self::NO_BIT;
         }
+        self.as_u128_bits().trailing_zeros()
     }
 
+    /// Returns the index of the highest set bit, as x86 `BSR`, or `NO_BIT` for zero.
     pub fn bitscanreverse(&self) -> u32 {
-        match self {
-            Self::Unsigned(x) => x.leading_zeros(),
-            Self::Signed(x) => x.leading_zeros(),
+        if self.is_zero() {
+            return Self::NO_BIT;
         }
+        BIT_SIZE as u32 - 1 - self.as_u128_bits().leading_zeros()
     }
 
     pub fn is_neg(&self) -> bool {
@@ -47,6 +55,67 @@ impl Const {
         }
     }
 
+    /// Returns the remainder of `self / rhs` which is never negative, as `i128::rem_euclid`.
+    /// Unlike `%`, whose result takes the sign of `self`, `-7` and `3` give `2`. The remainder of
+    /// `i128::MIN` by `-1`, whose quotient overflows, is `0`.
+    pub fn rem_euclid(self, rhs: Const) -> Const {
+        match self {
+            Self::Unsigned(x) => match rhs {
+                Self::Unsigned(y) => Self::Unsigned(x % y),
+                Self::Signed(y) => Self::Signed(Self::i128_saturating(x).rem_euclid(y)),
+            },
+            Self::Signed(x) => Self::Signed(x.wrapping_rem_euclid(rhs.as_i128_saturating())),
+        }
+    }
+
+    /// Builds a `Const` from raw bits with an explicitly chosen tag.
+    ///
+    /// With `signed` set the bits are read as a two's complement `i128`,
+    /// otherwise as a `u128`. Unlike the `From` impls, which pick the tag from
+    /// the Rust type, this lets callers decide signedness at runtime.
+    pub fn with_sign(value: u128, signed: bool) -> Const {
+        if signed {
+            Self::Signed(value as i128)
+        } else {
+            Self::Unsigned(value)
+        }
+    }
+
+    /// Adds `rhs` modulo 2^128, for elements using `.mode wrapping`.
+    pub fn wrapping_add(self, rhs: Const) -> Const {
+        match (self, rhs) {
+            (Self::Unsigned(x), Self::Unsigned(y)) => Self::Unsigned(x.wrapping_add(y)),
+            (x, y) => {
+                Self::Signed((x.as_u128_bits() as i128).wrapping_add(y.as_u128_bits() as i128))
+            }
+        }
+    }
+
+    /// Subtracts `rhs` modulo 2^128, for elements using `.mode wrapping`.
+    pub fn wrapping_sub(self, rhs: Const) -> Const {
+        match (self, rhs) {
+            (Self::Unsigned(x), Self::Unsigned(y)) => Self::Unsigned(x.wrapping_sub(y)),
+            (x, y) => {
+                Self::Signed((x.as_u128_bits() as i128).wrapping_sub(y.as_u128_bits() as i128))
+            }
+        }
+    }
+
+    /// Multiplies by `rhs` modulo 2^128, for elements using `.mode wrapping`.
+    pub fn wrapping_mul(self, rhs: Const) -> Const {
+        match (self, rhs) {
+            (Self::Unsigned(x), Self::Unsigned(y)) => Self::Unsigned(x.wrapping_mul(y)),
+            (x, y) => {
+                Self::Signed((x.as_u128_bits() as i128).wrapping_mul(y.as_u128_bits() as i128))
+            }
+        }
+    }
+
+    /// Compares the bits of `self` and `other` selected by `mask`, ignoring the sign.
+    pub fn eq_masked(&self, other: &Const, mask: u128) -> bool {
+        self.as_u128_bits() & mask == other.as_u128_bits() & mask
+    }
+
     fn as_u128_bits(&self) -> u128 {
         match self {
             Self::Unsigned(x) => *x,
@@ -82,7 +151,11 @@ impl Const {
         }
     }
 
+    /// Extracts the field, sign extending it when the field is declared signed.
     pub fn apply(self, f: &FieldSelector) -> Const {
+        if f.data_type == DataType::Signed {
+            return self.apply_signed(f);
+        }
         match self {
             Self::Unsigned(mut x) => {
                 if f.length == 0 {
@@ -111,12 +184,25 @@ impl Const {
         }
     }
 
+    /// Extracts the field as a two's complement signed value.
+    pub fn apply_signed(self, f: &FieldSelector) -> Const {
+        if f.length == 0 {
+            return Self::Signed(0);
+        }
+        let mut x = self.as_u128_bits() as i128;
+        x <<= BIT_SIZE - f.offset - f.length;
+        x >>= BIT_SIZE - f.length;
+        Self::with_sign(x as u128, true)
+    }
+
+    /// Stores `x` into the field, leaving every bit outside it untouched.
+    ///
+    /// Only the low `length` bits of `x` are kept, so wider values are truncated and a
+    /// zero length field stores nothing. The tag of `self` is kept whatever the tag of `x`.
     pub fn store(&mut self, x: Const, f: &FieldSelector) {
         let mut a = self.as_u128_bits();
-        let mut mask = (1u128 << f.length) - 1;
-        let mut b = x.as_u128_bits() & mask;
-        mask <<= f.offset;
-        b <<= f.offset;
+        let mask = f.mask();
+        let b = (x.as_u128_bits() << f.offset) & mask;
         // From https://graphics.stanford.edu/~seander/bithacks.html#MaskedMerge.
         a ^= (a ^ b) & mask;
         match self {
@@ -125,6 +211,23 @@ impl Const {
         }
     }
 
+    /// Stores `x` into the field as a two's complement value, the inverse of `apply_signed`.
+    ///
+    /// Values from `-2^(length-1)` to `2^(length-1)-1` read back unchanged. Others saturate to the
+    /// nearest of these bounds rather than being truncated as with `store`.
+    pub fn store_signed(&mut self, x: Const, f: &FieldSelector) {
+        if f.length == 0 {
+            return;
+        }
+        let min = -1i128 << (f.length - 1);
+        let max = !min;
+        let v = match x {
+            Self::Unsigned(x) => x.min(max as u128) as i128,
+            Self::Signed(x) => x.clamp(min, max),
+        };
+        self.store(Self::Signed(v), f)
+    }
+
     pub fn from_str_radix(src: &str, radix: u32) -> Result<Self, ParseIntError> {
         if src.starts_with("-") || src.starts_with("+") {
             Ok(Self::Signed(i128::from_str_radix(src, radix)?))
@@ -134,6 +237,9 @@ impl Const {
     }
 }
 
+// Conversions from Rust integers are tagged by type: unsigned types produce
+// `Const::Unsigned` and signed types produce `Const::Signed`, even when the
+// value is non-negative. Use `Const::with_sign` to choose the tag at runtime.
 macro_rules! from_numeric_uimpl {
     ($i:ident) => {
         impl From<$i> for Const {
@@ -237,6 +343,7 @@ impl Mul for Const {
     }
 }
 
+/// Saturates like `Mul` where the quotient overflows, so `i128::MIN / -1` is `i128::MAX`.
 impl Div for Const {
     type Output = Self;
 
@@ -246,11 +353,12 @@ impl Div for Const {
                 Self::Unsigned(y) => Self::Unsigned(x / y),
                 Self::Signed(y) => Self::Signed(Self::i128_saturating(x) / y),
             },
-            Self::Signed(x) => Self::Signed(x / rhs.as_i128_saturating()),
+            Self::Signed(x) => Self::Signed(x.saturating_div(rhs.as_i128_saturating())),
         }
     }
 }
 
+/// The remainder of `i128::MIN` by `-1`, whose quotient overflows, is `0`.
 impl Rem for Const {
     type Output = Self;
 
@@ -260,7 +368,7 @@ impl Rem for Const {
                 Self::Unsigned(y) => Self::Unsigned(x % y),
                 Self::Signed(y) => Self::Signed(Self::i128_saturating(x) % y),
             },
-            Self::Signed(x) => Self::Signed(x % rhs.as_i128_saturating()),
+            Self::Signed(x) => Self::Signed(x.wrapping_rem(rhs.as_i128_saturating())),
         }
     }
 }
@@ -357,6 +465,23 @@ impl PartialEq for Const {
 
 impl Eq for Const {}
 
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsigned(x) => write!(f, "{}", x),
+            Self::Signed(x) => write!(f, "{:+}", x),
+        }
+    }
+}
+
+impl Hash for Const {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_u128_bits().hash(state);
+    }
+}
+
+/// Orders by numeric value regardless of tag, so that every negative `Signed` is below every
+/// `Unsigned` and non-negative values compare by magnitude. This agrees with `PartialEq`.
 impl Ord for Const {
     fn cmp(&self, other: &Self) -> Ordering {
         match self {
@@ -400,6 +525,32 @@ mod tests {
         assert!(!Const::Signed(-1).is_zero());
     }
 
+    #[test]
+    fn test_eq_masked() {
+        assert!(Const::Unsigned(0b1010).eq_masked(&Const::Unsigned(0b0010), 0b0111));
+        assert!(!Const::Unsigned(0b1010).eq_masked(&Const::Unsigned(0b0011), 0b0111));
+        assert!(Const::Signed(-1).eq_masked(&Const::Unsigned(u128::MAX), u128::MAX));
+    }
+
+    #[test]
+    fn test_wrapping() {
+        let max = Const::Unsigned(u128::MAX);
+        assert_eq!(u128::from(max + Const::Unsigned(1)), u128::MAX);
+        assert_eq!(u128::from(max.wrapping_add(Const::Unsigned(1))), 0);
+        assert_eq!(
+            u128::from(Const::Unsigned(0).wrapping_sub(Const::Unsigned(1))),
+            u128::MAX
+        );
+        assert_eq!(
+            i128::from(Const::Signed(i128::MAX).wrapping_add(Const::Signed(1))),
+            i128::MIN
+        );
+        assert_eq!(
+            u128::from(max.wrapping_mul(Const::Unsigned(2))),
+            u128::MAX - 1
+        );
+    }
+
     #[test]
     fn test_count_ones() {
         assert_eq!(Const::Unsigned(0).count_ones(), 0);
@@ -414,14 +565,24 @@ mod tests {
         assert_eq!(Const::Signed(0).bitscanforward(), 128);
         assert_eq!(Const::Unsigned(3).bitscanforward(), 0);
         assert_eq!(Const::Signed(3).bitscanforward(), 0);
+        assert_eq!(Const::Signed(-4).bitscanforward(), 2);
+        for k in 0..128 {
+            assert_eq!(Const::Unsigned(1 << k).bitscanforward(), k);
+            assert_eq!(Const::Signed(1 << k).bitscanforward(), k);
+        }
     }
 
     #[test]
     fn test_bitscanreverse() {
         assert_eq!(Const::Unsigned(0).bitscanreverse(), 128);
         assert_eq!(Const::Signed(0).bitscanreverse(), 128);
-        assert_eq!(Const::Unsigned(3).bitscanreverse(), 126);
-        assert_eq!(Const::Signed(3).bitscanreverse(), 126);
+        assert_eq!(Const::Unsigned(3).bitscanreverse(), 1);
+        assert_eq!(Const::Signed(3).bitscanreverse(), 1);
+        assert_eq!(Const::Signed(-1).bitscanreverse(), 127);
+        for k in 0..128 {
+            assert_eq!(Const::Unsigned(1 << k).bitscanreverse(), k);
+            assert_eq!(Const::Signed(1 << k).bitscanreverse(), k);
+        }
     }
 
     #[test]
@@ -446,35 +607,145 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_cmp_boundaries() {
+        use Ordering::*;
+        let cases = [
+            (
+                Const::Unsigned(u128::MAX),
+                Const::Signed(i128::MAX),
+                Greater,
+            ),
+            (
+                Const::Unsigned(i128::MAX as u128),
+                Const::Signed(i128::MAX),
+                Equal,
+            ),
+            (
+                Const::Unsigned(i128::MAX as u128 + 1),
+                Const::Signed(i128::MAX),
+                Greater,
+            ),
+            (Const::Unsigned(0), Const::Signed(i128::MIN), Greater),
+            (Const::Unsigned(0), Const::Signed(-1), Greater),
+            (Const::Unsigned(0), Const::Signed(0), Equal),
+            (Const::Signed(-1), Const::Unsigned(u128::MAX), Less),
+            (Const::Signed(i128::MIN), Const::Signed(i128::MAX), Less),
+        ];
+        for (a, b, want) in cases.iter() {
+            assert_eq!(a.cmp(b), *want, "{} vs {}", a, b);
+            assert_eq!(b.cmp(a), want.reverse(), "{} vs {}", b, a);
+            assert_eq!(a == b, *want == Equal, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_cmp_matches_reference() {
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+
+        // A wider integer as (negative, magnitude), ordered like an i256.
+        fn reference(x: Const) -> (bool, u128) {
+            match x {
+                Const::Unsigned(x) => (false, x),
+                Const::Signed(x) => (x < 0, x.unsigned_abs()),
+            }
+        }
+        fn reference_cmp(a: Const, b: Const) -> Ordering {
+            match (reference(a), reference(b)) {
+                ((true, x), (true, y)) => y.cmp(&x),
+                ((true, _), (false, _)) => Ordering::Less,
+                ((false, _), (true, _)) => Ordering::Greater,
+                ((false, x), (false, y)) => x.cmp(&y),
+            }
+        }
+
+        let edges = [0, 1, i128::MAX as u128, i128::MAX as u128 + 1, u128::MAX];
+        let mut rng = SmallRng::seed_from_u64(1547);
+        let sample = |rng: &mut SmallRng| {
+            let bits = if rng.gen_bool(0.5) {
+                edges[rng.gen_range(0..edges.len())]
+                    .wrapping_add(rng.gen_range(0..3))
+                    .wrapping_sub(1)
+            } else {
+                rng.gen::<u128>() >> rng.gen_range(0..128)
+            };
+            Const::with_sign(bits, rng.gen_bool(0.5))
+        };
+        for _ in 0..10000 {
+            let (a, b) = (sample(&mut rng), sample(&mut rng));
+            let want = reference_cmp(a, b);
+            assert_eq!(a.cmp(&b), want, "{} vs {}", a, b);
+            assert_eq!(a == b, want == Ordering::Equal, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_mul_saturates() {
+        assert_eq!(
+            Const::Unsigned(u128::MAX / 2) * Const::Unsigned(3),
+            Const::Unsigned(u128::MAX)
+        );
+        assert_eq!(
+            Const::Signed(i128::MIN / 2) * Const::Signed(3),
+            Const::Signed(i128::MIN)
+        );
+        assert_eq!(
+            Const::Unsigned(u128::MAX) * Const::Signed(-2),
+            Const::Signed(i128::MIN)
+        );
+    }
+
+    #[test]
+    fn test_div_overflow() {
+        assert_eq!(
+            Const::Signed(i128::MIN) / Const::Signed(-1),
+            Const::Signed(i128::MAX)
+        );
+        assert_eq!(
+            Const::Signed(i128::MIN) % Const::Signed(-1),
+            Const::Signed(0)
+        );
+        assert_eq!(
+            Const::Signed(i128::MIN) / Const::Signed(2),
+            Const::Signed(i128::MIN / 2)
+        );
+    }
+
     #[test]
     fn test_apply_unsigned() {
         let mut x = Const::Unsigned(1).apply(&FieldSelector {
             offset: 0,
             length: 0,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Unsigned(0));
 
         x = Const::Unsigned(1).apply(&FieldSelector {
             offset: 0,
             length: 1,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Unsigned(1));
 
         x = Const::Unsigned(2).apply(&FieldSelector {
             offset: 0,
             length: 1,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Unsigned(0));
 
         x = Const::Unsigned(2).apply(&FieldSelector {
             offset: 0,
             length: 3,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Unsigned(2));
 
         x = Const::Unsigned(1 << 64).apply(&FieldSelector {
             offset: 0,
             length: 20,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Unsigned(0));
 
@@ -487,54 +758,63 @@ mod tests {
         let mut x = Const::Signed(-1).apply(&FieldSelector {
             offset: 0,
             length: 0,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Signed(0));
 
         x = Const::Signed(-1).apply(&FieldSelector {
             offset: 0,
             length: 1,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Signed(0));
 
         x = Const::Signed(2).apply(&FieldSelector {
             offset: 0,
             length: 1,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Signed(0));
 
         x = Const::Signed(2).apply(&FieldSelector {
             offset: 0,
             length: 3,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Signed(2));
 
         x = Const::Signed(1 << 64).apply(&FieldSelector {
             offset: 0,
             length: 20,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Signed(0));
 
         x = Const::Signed(-1).apply(&FieldSelector {
             offset: 0,
             length: 1,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Signed(0));
 
         x = Const::Signed(-2).apply(&FieldSelector {
             offset: 0,
             length: 1,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Signed(0));
 
         x = Const::Signed(-2).apply(&FieldSelector {
             offset: 0,
             length: 3,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Signed(-2));
 
         x = Const::Signed(-1 << 64).apply(&FieldSelector {
             offset: 0,
             length: 20,
+            data_type: DataType::Unsigned,
         });
         assert_eq!(x, Const::Signed(0));
     }
@@ -547,6 +827,7 @@ mod tests {
             &FieldSelector {
                 offset: 0,
                 length: 1,
+                data_type: DataType::Unsigned,
             },
         );
         assert_eq!(x, Const::Unsigned(0));
@@ -557,6 +838,7 @@ mod tests {
             &FieldSelector {
                 offset: 1,
                 length: 1,
+                data_type: DataType::Unsigned,
             },
         );
         assert_eq!(x, Const::Unsigned(3));
@@ -567,8 +849,121 @@ mod tests {
             &FieldSelector {
                 offset: 1,
                 length: 4,
+                data_type: DataType::Unsigned,
             },
         );
         assert_eq!(x, Const::Unsigned(0b111011));
     }
+
+    #[test]
+    fn test_store_signed() {
+        for &(offset, length) in &[(0, 1), (4, 4), (3, 8), (0, 71), (25, 71)] {
+            let f = FieldSelector {
+                offset,
+                length,
+                data_type: DataType::Signed,
+            };
+            let min = -1i128 << (length - 1);
+            let max = !min;
+            for &v in &[min, min + 1, -1, 0, max] {
+                let mut x = Const::Unsigned(0);
+                x.store_signed(Const::Signed(v), &f);
+                assert_eq!(x.apply_signed(&f), Const::Signed(v), "{} in {}", v, f);
+                assert_eq!(x.apply(&f), Const::Signed(v), "{} in {}", v, f);
+            }
+        }
+
+        // Out of range values saturate and neighbouring bits are kept.
+        let f = FieldSelector {
+            offset: 4,
+            length: 4,
+            data_type: DataType::Signed,
+        };
+        let mut x = Const::Unsigned(0xfff);
+        x.store_signed(Const::Signed(-1), &f);
+        assert!(matches!(x, Const::Unsigned(0xfff)));
+        x.store_signed(Const::Signed(8), &f);
+        assert!(matches!(x, Const::Unsigned(0xf7f)));
+        assert_eq!(x.apply_signed(&f), Const::Signed(7));
+        x.store_signed(Const::Signed(-9), &f);
+        assert_eq!(x.apply_signed(&f), Const::Signed(-8));
+        x.store_signed(Const::Unsigned(u128::MAX), &f);
+        assert_eq!(x.apply_signed(&f), Const::Signed(7));
+        x.store_signed(Const::Signed(i128::MIN), &f);
+        assert_eq!(x.apply_signed(&f), Const::Signed(-8));
+    }
+
+    #[test]
+    fn test_store_edges() {
+        let field = |offset, length| FieldSelector {
+            offset,
+            length,
+            data_type: DataType::Unsigned,
+        };
+
+        let mut x = Const::Unsigned(0xabcd);
+        x.store(Const::Unsigned(u128::MAX), &field(4, 0));
+        assert!(matches!(x, Const::Unsigned(0xabcd)));
+
+        // The type field reaches bit 95.
+        let mut x = Const::Unsigned(!0 ^ (0xffff << 80));
+        x.store(Const::Unsigned(0x1234), &FieldSelector::TYPE);
+        assert!(matches!(x, Const::Unsigned(y) if y == !0 ^ (0xedcb << 80)));
+        assert_eq!(x.apply(&FieldSelector::TYPE), Const::Unsigned(0x1234));
+
+        // Wider values are truncated to the field.
+        let mut x = Const::Unsigned(0);
+        x.store(Const::Unsigned(0x1ff), &field(8, 4));
+        assert!(matches!(x, Const::Unsigned(0xf00)));
+        let mut x = Const::Unsigned(0);
+        x.store(Const::Signed(-1), &field(120, 8));
+        assert!(matches!(x, Const::Unsigned(y) if y == 0xff << 120));
+
+        let mut x = Const::Signed(-1);
+        x.store(Const::Unsigned(0), &field(0, 128));
+        assert!(matches!(x, Const::Signed(0)));
+    }
+
+    #[test]
+    fn test_rem_euclid() {
+        assert_eq!(Const::Signed(-7) % Const::Unsigned(3), Const::Signed(-1));
+        assert_eq!(
+            Const::Signed(-7).rem_euclid(Const::Unsigned(3)),
+            Const::Signed(2)
+        );
+        assert_eq!(
+            Const::Signed(-7).rem_euclid(Const::Signed(-3)),
+            Const::Signed(2)
+        );
+        assert_eq!(
+            Const::Signed(7).rem_euclid(Const::Signed(-3)),
+            Const::Signed(1)
+        );
+        assert_eq!(
+            Const::Unsigned(7).rem_euclid(Const::Unsigned(3)),
+            Const::Unsigned(1)
+        );
+        assert_eq!(
+            Const::Signed(i128::MIN).rem_euclid(Const::Signed(-1)),
+            Const::Signed(0)
+        );
+    }
+
+    #[test]
+    fn test_with_sign() {
+        assert_eq!(Const::with_sign(5, false), Const::Unsigned(5));
+        assert_eq!(Const::with_sign(5, true), Const::Signed(5));
+        assert_eq!(Const::with_sign(5, true), Const::with_sign(5, false));
+
+        let bits = u128::MAX;
+        let u = Const::with_sign(bits, false);
+        let i = Const::with_sign(bits, true);
+        assert!(matches!(u, Const::Unsigned(u128::MAX)));
+        assert!(matches!(i, Const::Signed(-1)));
+        assert!(i.is_neg());
+        assert_ne!(u, i);
+        assert!(i < u);
+        assert!(i < Const::Unsigned(0));
+        assert!(u > Const::Signed(i128::MAX));
+    }
 }
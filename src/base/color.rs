@@ -2,7 +2,8 @@ use std::num::ParseIntError;
 use std::str::FromStr;
 use thiserror;
 
-#[derive(Copy, Clone, Debug)]
+/// A 32 bit color laid out as `0xRRGGBBAA`, the same order as hex color strings and RGBA pixels.
+#[derive(Copy, Clone, Debug, Hash)]
 pub struct Color(u32);
 
 impl From<u32> for Color {
@@ -16,6 +17,10 @@ impl Color {
     Self(0)
   }
 
+  pub fn from_components(r: u8, g: u8, b: u8, a: u8) -> Self {
+    Self(u32::from_be_bytes([r, g, b, a]))
+  }
+
   pub fn bits(&self) -> u32 {
     return self.0;
   }
@@ -28,6 +33,10 @@ impl Color {
       (self.0 & 0xff) as u8,
     );
   }
+
+  pub fn alpha(&self) -> u8 {
+    (self.0 & 0xff) as u8
+  }
 }
 
 #[derive(Debug, Clone, thiserror::Error)]
@@ -41,10 +50,13 @@ pub enum ParseColorError {
 impl FromStr for Color {
   type Err = ParseColorError;
 
+  /// Parses `RRGGBBAA`, `RRGGBB` or `RGB` hex colors with an optional leading `#`.
+  /// Colors without an alpha channel are opaque.
   fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.strip_prefix('#').unwrap_or(s);
     match s.len() {
-      9 => Ok(u32::from_str_radix(s, 16)?.into()),
-      6 => Ok((u32::from_str_radix(s, 16)? << 8).into()),
+      8 => Ok(u32::from_str_radix(s, 16)?.into()),
+      6 => Ok((u32::from_str_radix(s, 16)? << 8 | 0xff).into()),
       3 => {
         let v = u32::from_str_radix(s, 16)?;
         // abc => aabbccff
@@ -54,3 +66,30 @@ impl FromStr for Color {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_components() {
+    let c = Color::from_str("#11223344").unwrap();
+    assert_eq!(c.components(), (0x11, 0x22, 0x33, 0x44));
+    assert_eq!(c.alpha(), 0x44);
+    assert_eq!(Color::from_str("11223344").unwrap().bits(), 0x11223344);
+    assert_eq!(
+      Color::from_components(0x11, 0x22, 0x33, 0x44).bits(),
+      0x11223344
+    );
+  }
+
+  #[test]
+  fn test_opaque_without_alpha() {
+    assert_eq!(Color::from_str("112233").unwrap().bits(), 0x112233ff);
+    assert_eq!(Color::from_str("#123").unwrap().bits(), 0x112233ff);
+    assert!(matches!(
+      Color::from_str("#1234"),
+      Err(ParseColorError::BadLength(4))
+    ));
+  }
+}
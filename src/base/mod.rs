@@ -3,8 +3,51 @@ pub mod color;
 
 use bitflags::bitflags;
 use std::fmt;
+use std::hash::Hasher;
 use std::str::FromStr;
 
+/// A 32 bit FNV-1a `Hasher`, which unlike the std hashers gives the same result on every build.
+pub struct Fnv1a(u32);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0x811c9dc5)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u32).wrapping_mul(0x01000193);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
+/// Hashes a label or parameter name where bytecode refers to it by name, with `Fnv1a`.
+pub fn name_hash(name: &str) -> u32 {
+    let mut h = Fnv1a::default();
+    h.write(name.as_bytes());
+    h.0
+}
+
+/// The largest radius the 41 site event window covers.
+pub const MAX_RADIUS: u8 = 4;
+
+/// Returns the number of sites, including the origin, within the given radius.
+pub fn window_size(radius: u8) -> usize {
+    match radius {
+        0 => 1,
+        1 => 5,
+        2 => 13,
+        3 => 25,
+        _ => 41,
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
 pub struct SiteNumber(pub u8);
 
@@ -14,39 +57,80 @@ impl fmt::Display for SiteNumber {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+/// How the bits of a field are read.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, PartialOrd)]
+pub enum DataType {
+    Unsigned,
+    /// Two's complement, sign extended when read.
+    Signed,
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, PartialOrd)]
 pub struct FieldSelector {
     pub offset: u8,
     pub length: u8,
+    pub data_type: DataType,
 }
 
 impl FieldSelector {
     pub const TYPE: Self = Self {
         offset: 80,
         length: 16,
+        data_type: DataType::Unsigned,
     };
     pub const HEADER: Self = Self {
         offset: 71,
         length: 25,
+        data_type: DataType::Unsigned,
     };
     pub const DATA: Self = Self {
         offset: 0,
         length: 71,
+        data_type: DataType::Unsigned,
+    };
+    pub const CHECKSUM: Self = Self {
+        offset: 71,
+        length: 9,
+        data_type: DataType::Unsigned,
     };
+
+    /// Returns a mask of the bits covered by the field.
+    pub fn mask(&self) -> u128 {
+        if self.length == 0 {
+            return 0;
+        }
+        (u128::MAX >> (128 - self.length as u32)) << self.offset
+    }
+}
+
+impl fmt::Display for FieldSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.data_type {
+            DataType::Unsigned => write!(f, "{{{}:{}}}", self.offset, self.length),
+            DataType::Signed => write!(f, "{{{}:{}:signed}}", self.offset, self.length),
+        }
+    }
 }
 
+// Offsets never exceed 127, which leaves the high bit of the offset byte to mark signed fields.
 impl From<u16> for FieldSelector {
     fn from(x: u16) -> Self {
         Self {
-            offset: x as u8,
+            offset: x as u8 & 0x7f,
             length: (x >> 8) as u8,
+            data_type: if x & 0x80 != 0 {
+                DataType::Signed
+            } else {
+                DataType::Unsigned
+            },
         }
     }
 }
 
 impl From<FieldSelector> for u16 {
     fn from(x: FieldSelector) -> u16 {
-        (x.offset as u16) | (x.length as u16) << 8
+        let signed = (x.data_type == DataType::Signed) as u16;
+        (x.offset as u16) | signed << 7 | (x.length as u16) << 8
     }
 }
 
@@ -57,8 +141,8 @@ bitflags! {
     const R180L = 0x4; // Flip_XY.
     const R270L = 0x8;
     const R000R = 0x10; // Flip_Y.
-    const R090R = 0x20; // Flip_X.
-    const R180R = 0x40;
+    const R090R = 0x20;
+    const R180R = 0x40; // Flip_X.
     const R270R = 0x80;
   }
 }
@@ -82,6 +166,35 @@ impl FromStr for Symmetries {
     }
 }
 
+impl fmt::Display for Symmetries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "NONE");
+        }
+        if self.is_all() {
+            return write!(f, "ALL");
+        }
+        let names = [
+            (Symmetries::R000L, "R000L"),
+            (Symmetries::R090L, "R090L"),
+            (Symmetries::R180L, "R180L"),
+            (Symmetries::R270L, "R270L"),
+            (Symmetries::R000R, "R000R"),
+            (Symmetries::R090R, "R090R"),
+            (Symmetries::R180R, "R180R"),
+            (Symmetries::R270R, "R270R"),
+        ];
+        let mut sep = "";
+        for (s, name) in names.iter() {
+            if self.contains(*s) {
+                write!(f, "{}{}", sep, name)?;
+                sep = " | ";
+            }
+        }
+        Ok(())
+    }
+}
+
 impl From<u8> for Symmetries {
     fn from(x: u8) -> Self {
         Self { bits: x }
@@ -7,14 +7,18 @@ mod base;
 #[path = "../ast.rs"]
 mod ast;
 
+use crate::base::arith::Const;
+use crate::base::window_size;
 use crate::runtime::mfm::{
-  debug_event_window, select_symmetries, EventWindow, MinimalEventWindow, Rand,
+  debug_event_window, select_symmetries, site_at, EventWindow, Metadata, MinimalEventWindow, Rand,
 };
 use crate::runtime::{Cursor, Runtime};
 use clap::arg_enum;
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::io;
 use std::io::BufReader;
 use std::path::Path;
 use stderrlog;
@@ -75,6 +79,13 @@ struct Cli {
   )]
   seed_element: Option<String>,
 
+  #[structopt(
+    long = "place",
+    help = "Place the input element at offset X,Y from the window center instead of at the center. May be repeated.",
+    parse(try_from_str = parse_offset)
+  )]
+  places: Vec<(isize, isize)>,
+
   #[structopt(
     long = "test",
     short = "t",
@@ -135,21 +146,306 @@ fn main() {
   ewar_main(&args);
 }
 
+fn parse_offset(s: &str) -> Result<(isize, isize), String> {
+  let mut parts = s.splitn(2, ',');
+  let x = parts.next().unwrap_or_default();
+  let y = parts.next().ok_or_else(|| format!("expected X,Y: {}", s))?;
+  match (x.trim().parse(), y.trim().parse()) {
+    (Ok(x), Ok(y)) => Ok((x, y)),
+    _ => Err(format!("bad offset: {}", s)),
+  }
+}
+
+/// Places `atom` at each offset in `places`, or at the center if there are none.
+///
+/// Returns the first offset which lies outside the event window.
+fn place<T: EventWindow>(
+  ew: &mut T,
+  places: &[(isize, isize)],
+  atom: Const,
+) -> Result<(), (isize, isize)> {
+  if places.is_empty() {
+    ew.set(0, atom);
+  }
+  for &(x, y) in places {
+    ew.set(site_at(x, y).ok_or((x, y))? as usize, atom);
+  }
+  Ok(())
+}
+
+/// Executes `seed` once from the center of the window.
+fn seed_event_window<T: EventWindow + Rand>(
+  ew: &mut T,
+  runtime: &Runtime,
+  seed: &Metadata,
+) -> Result<(), runtime::Error> {
+  let mut cursor = Cursor::with_symmetry(select_symmetries(ew.rand_u32(), seed.symmetries));
+  ew.set(0, seed.new_atom());
+  Runtime::execute(ew, &mut cursor, &runtime.code_map, &runtime.type_map)
+}
+
+/// Outcomes tallied over every trial.
+#[derive(Debug, Default)]
+struct TrialStats {
+  trials: u32,
+  /// Trials whose input element ended with an explicit `exit`.
+  exited: u32,
+  /// Events executed, counting the seed element.
+  events: u64,
+  /// Failed trials keyed by error message.
+  errors: BTreeMap<String, u32>,
+}
+
+impl TrialStats {
+  fn failed(&self) -> u32 {
+    self.errors.values().sum()
+  }
+
+  fn average_events(&self) -> f64 {
+    if self.trials == 0 {
+      return 0.0;
+    }
+    self.events as f64 / self.trials as f64
+  }
+
+  fn write_summary<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+    writeln!(w, "trials: {}", self.trials)?;
+    writeln!(w, "exited: {}", self.exited)?;
+    writeln!(
+      w,
+      "completed: {}",
+      self.trials - self.exited - self.failed()
+    )?;
+    writeln!(w, "errors: {}", self.failed())?;
+    for (e, n) in &self.errors {
+      writeln!(w, "  {}: {}", n, e)?;
+    }
+    writeln!(w, "average events: {:.2}", self.average_events())
+  }
+}
+
+/// Clears the window, runs `seed` if any, places `init` and executes it once from the center.
+fn run_trial<T: EventWindow + Rand>(
+  ew: &mut T,
+  runtime: &Runtime,
+  init: &Metadata,
+  seed: Option<&Metadata>,
+  places: &[(isize, isize)],
+  stats: &mut TrialStats,
+) {
+  for i in 0..window_size(4) {
+    ew.set(i, 0u8.into());
+  }
+  stats.trials += 1;
+  let mut run = || -> Result<bool, runtime::Error> {
+    if let Some(seed) = seed {
+      stats.events += 1;
+      seed_event_window(ew, runtime, seed)?;
+      ew.set(0, 0u8.into());
+    }
+    // Offsets were checked before the first trial.
+    place(ew, places, init.new_atom()).ok();
+    let mut cursor = Cursor::new();
+    runtime.reset_cursor(ew, &mut cursor);
+    stats.events += 1;
+    Runtime::execute(ew, &mut cursor, &runtime.code_map, &runtime.type_map)?;
+    Ok(cursor.exited())
+  };
+  match run() {
+    Ok(true) => stats.exited += 1,
+    Ok(false) => {}
+    Err(e) => *stats.errors.entry(e.to_string()).or_default() += 1,
+  }
+}
+
+/// Returns the sites of the event window as big-endian 128 bit words.
+fn window_bytes<T: EventWindow>(ew: &T) -> Vec<u8> {
+  (0..window_size(4))
+    .flat_map(|i| u128::from(ew.get(i)).to_be_bytes().to_vec())
+    .collect()
+}
+
+/// Encodes `data` as padded base64 with the standard alphabet (RFC 4648).
+fn encode_b64(data: &[u8]) -> String {
+  const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut s = String::with_capacity(data.len().div_ceil(3) * 4);
+  for chunk in data.chunks(3) {
+    let b = [
+      chunk[0],
+      *chunk.get(1).unwrap_or(&0),
+      *chunk.get(2).unwrap_or(&0),
+    ];
+    let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+    for i in 0..4 {
+      if i <= chunk.len() {
+        s.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+      } else {
+        s.push('=');
+      }
+    }
+  }
+  s
+}
+
 fn ewar_main(args: &Cli) {
   let mut runtime = Runtime::new();
 
   let mut file = File::open(Path::new::<String>(&args.input)).expect("Failed to open input file");
   let mut r = BufReader::new(&mut file);
   let init = runtime
-    .load_from_reader(&mut r)
-    .expect("Failed to process input file");
+    .load_all_from_reader(&mut r)
+    .expect("Failed to process input file")
+    .into_iter()
+    .next()
+    .expect("Input file has no elements");
+  let seed = args.seed_element.as_ref().map(|seed| {
+    let mut file = File::open(Path::new::<String>(seed)).expect("Failed to open seed element");
+    let mut r = BufReader::new(&mut file);
+    runtime
+      .load_from_reader(&mut r)
+      .expect("Failed to process seed element")
+  });
 
-  let mut rng = SmallRng::from_entropy();
+  if let Some(&(x, y)) = args.places.iter().find(|&&(x, y)| site_at(x, y).is_none()) {
+    eprintln!("--place {},{} is outside the event window.", x, y);
+    std::process::exit(1);
+  }
+
+  let mut rng = SmallRng::seed_from_u64(args.random_seed);
   let mut ew = MinimalEventWindow::new(&mut rng);
-  let s = select_symmetries(ew.rand_u32(), init.symmetries);
-  let mut cursor = Cursor::with_symmetry(s);
-  ew.set(0, init.new_atom());
-  Runtime::execute(&mut ew, &mut cursor, &runtime.code_map).expect("Failed to execute");
-  debug_event_window(&ew, &mut std::io::stdout(), &runtime.type_map)
-    .expect("Failed to debug event window");
+  let mut stats = TrialStats::default();
+  for _ in 0..args.n {
+    run_trial(
+      &mut ew,
+      &runtime,
+      &init,
+      seed.as_ref(),
+      &args.places,
+      &mut stats,
+    );
+  }
+
+  let mut out = io::stdout();
+  debug_event_window(&ew, &mut out, &runtime.type_map).expect("Failed to debug event window");
+  stats
+    .write_summary(&mut out)
+    .expect("Failed to write summary");
+
+  if let Some(expect) = &args.expect {
+    let got = encode_b64(&window_bytes(&ew));
+    if got == expect.trim() {
+      println!("PASS");
+    } else {
+      println!("FAIL: got {}", got);
+      std::process::exit(1);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ast::Instruction;
+  use rand::rngs::mock::StepRng;
+
+  #[test]
+  fn test_seed_event_window() {
+    let mut runtime = Runtime::new();
+    let mut init = Metadata::new();
+    init.type_num = 1;
+    let mut seed = Metadata::new();
+    seed.type_num = 2;
+    seed.radius = 1;
+    runtime.type_map.insert(1, init.clone());
+    runtime.type_map.insert(2, seed.clone());
+    runtime.code_map.insert(1, vec![]);
+    let mut code = Vec::new();
+    for i in 1..5 {
+      code.extend_from_slice(&[
+        Instruction::Push(i.into()),
+        Instruction::Push0,
+        Instruction::GetSite,
+        Instruction::SetSite,
+      ]);
+    }
+    runtime.code_map.insert(2, code);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    seed_event_window(&mut ew, &runtime, &seed).unwrap();
+    place(&mut ew, &[], init.new_atom()).unwrap();
+    assert_eq!(ew.get(0), init.new_atom());
+    for i in 1..5 {
+      assert_eq!(ew.get(i), seed.new_atom());
+    }
+    assert!(ew.get(5).is_zero());
+  }
+
+  #[test]
+  fn test_run_trial() {
+    let mut runtime = Runtime::new();
+    let mut init = Metadata::new();
+    init.type_num = 1;
+    runtime.type_map.insert(1, init.clone());
+    runtime.code_map.insert(1, vec![Instruction::Exit]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    let mut stats = TrialStats::default();
+    ew.set(3, init.new_atom());
+    run_trial(&mut ew, &runtime, &init, None, &[], &mut stats);
+    assert!(ew.get(3).is_zero());
+    runtime.code_map.insert(1, vec![Instruction::Add]);
+    run_trial(&mut ew, &runtime, &init, None, &[], &mut stats);
+    runtime.code_map.insert(1, vec![]);
+    run_trial(&mut ew, &runtime, &init, Some(&init), &[], &mut stats);
+    assert_eq!(stats.trials, 3);
+    assert_eq!(stats.exited, 1);
+    assert_eq!(stats.failed(), 1);
+    assert_eq!(stats.events, 4);
+    let mut v = Vec::new();
+    stats.write_summary(&mut v).unwrap();
+    let summary = String::from_utf8(v).unwrap();
+    assert!(summary.contains("completed: 1\n"), "{}", summary);
+    assert!(summary.contains("average events: 1.33\n"), "{}", summary);
+  }
+
+  #[test]
+  fn test_window_bytes() {
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(1, 0x0102u16.into());
+    let v = window_bytes(&ew);
+    assert_eq!(v.len(), 41 * 16);
+    assert_eq!(&v[30..32], &[1, 2]);
+    assert!(v.iter().filter(|&&b| b != 0).count() == 2);
+  }
+
+  #[test]
+  fn test_encode_b64() {
+    for (data, want) in [
+      ("", ""),
+      ("f", "Zg=="),
+      ("fo", "Zm8="),
+      ("foo", "Zm9v"),
+      ("foob", "Zm9vYg=="),
+      ("fooba", "Zm9vYmE="),
+      ("foobar", "Zm9vYmFy"),
+    ]
+    .iter()
+    {
+      assert_eq!(encode_b64(data.as_bytes()), *want);
+    }
+  }
+
+  #[test]
+  fn test_place() {
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    let atom = Const::from(1u8);
+    place(&mut ew, &[(-1, 0), (2, 1)], atom).unwrap();
+    assert_eq!(ew.get(1), atom);
+    assert_eq!(ew.get(20), atom);
+    assert!(ew.get(0).is_zero());
+    assert_eq!(place(&mut ew, &[(4, 1)], atom), Err((4, 1)));
+    assert_eq!(parse_offset("-1,2"), Ok((-1, 2)));
+  }
 }
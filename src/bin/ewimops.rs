@@ -7,18 +7,27 @@ mod base;
 #[path = "../ast.rs"]
 mod ast;
 
-use crate::runtime::mfm::{select_symmetries, DenseGrid, EventWindow, Rand, SparseGrid};
+#[cfg(test)]
+#[path = "../code.rs"]
+mod code;
+
+use crate::base::arith::Const;
+use crate::base::color::Color;
+use crate::runtime::mfm::{DenseGrid, EventWindow, Rand, SparseGrid};
 use crate::runtime::{Cursor, Runtime};
+use colored::Colorize;
+use image::gif::{GifEncoder, Repeat};
 use image::io::Reader as ImageReader;
-use image::{DynamicImage, GenericImageView};
-use log::trace;
+use image::{Delay, DynamicImage, Frame, GenericImageView, RgbaImage};
+use log::{trace, warn};
 use rand::rngs::SmallRng;
-use rand::SeedableRng;
+use rand::{RngCore, SeedableRng};
 use runtime::mfm::Blit;
 use std::fs;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
+use std::process::exit;
 use stderrlog;
 use structopt::StructOpt;
 
@@ -35,6 +44,66 @@ struct Cli {
     )]
     output: Option<String>,
 
+    #[structopt(
+        long = "gif",
+        help = "Output file name for an animated GIF of the grid paint captured during the run."
+    )]
+    gif: Option<String>,
+
+    #[structopt(
+        long = "frame-every",
+        help = "Number of events between captured GIF frames.",
+        default_value = "100000"
+    )]
+    frame_every: u64,
+
+    #[structopt(
+        long = "frame-delay",
+        help = "Display time of each GIF frame in milliseconds.",
+        default_value = "100"
+    )]
+    frame_delay: u32,
+
+    #[structopt(
+        long = "gif-loops",
+        help = "Number of times the GIF animation repeats. 0 repeats forever.",
+        default_value = "0"
+    )]
+    gif_loops: u16,
+
+    #[structopt(
+        long = "preview",
+        help = "Print an ANSI color preview of the final grid paint to the terminal."
+    )]
+    preview: bool,
+
+    #[structopt(
+        long = "preview-width",
+        help = "Maximum preview width in columns. Defaults to $COLUMNS, or 80."
+    )]
+    preview_width: Option<usize>,
+
+    #[structopt(
+        long = "progress-every",
+        help = "Number of events between status lines. 0 disables status output.",
+        default_value = "1000000"
+    )]
+    progress_every: u64,
+
+    #[structopt(
+        long = "max-events",
+        help = "Maximum number of events to run.",
+        default_value = "10000000"
+    )]
+    max_events: u64,
+
+    #[structopt(
+        long = "quiescence-window",
+        help = "Stop early after this many events in a row change nothing. Events on empty sites are not counted, but an atom which changes something only rarely may still be taken for quiet. 0 never stops early.",
+        default_value = "0"
+    )]
+    quiescence_window: u64,
+
     #[structopt(
         long = "init",
         help = "A compiled EWAL program which initializes the image operation."
@@ -47,6 +116,20 @@ struct Cli {
     )]
     ops: Vec<String>,
 
+    #[structopt(
+        long = "seed",
+        help = "Scatter COUNT atoms of the loaded element NAME over empty cells, as NAME=COUNT.",
+        parse(try_from_str = parse_seed)
+    )]
+    seeds: Vec<(String, usize)>,
+
+    #[structopt(
+        long = "place",
+        help = "Place the init element at cell X,Y instead of the grid origin. May be repeated.",
+        parse(try_from_str = parse_point)
+    )]
+    places: Vec<(usize, usize)>,
+
     #[structopt(
         long = "grid-scale",
         help = "Grid scale factor relative to the input image.",
@@ -73,6 +156,20 @@ struct Cli {
     verbose: usize,
 }
 
+/// Number of random cells tried for each seeded atom before giving up.
+const MAX_SEED_TRIES: usize = 64;
+
+fn parse_seed(s: &str) -> Result<(String, usize), String> {
+    let mut parts = s.splitn(2, '=');
+    let name = parts.next().unwrap_or_default();
+    let count = parts
+        .next()
+        .ok_or_else(|| format!("expected NAME=COUNT: {}", s))?
+        .parse()
+        .map_err(|e| format!("bad seed count: {}", e))?;
+    Ok((name.to_owned(), count))
+}
+
 fn main() {
     let args = Cli::from_args();
     stderrlog::new()
@@ -83,7 +180,102 @@ fn main() {
     ewimops_main(&args);
 }
 
-fn ewimops_main(args: &Cli) {
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn parse_point(s: &str) -> Result<(usize, usize), String> {
+    let mut parts = s.splitn(2, ',');
+    let x = parts.next().unwrap_or_default();
+    let y = parts.next().ok_or_else(|| format!("expected X,Y: {}", s))?;
+    match (x.trim().parse(), y.trim().parse()) {
+        (Ok(x), Ok(y)) => Ok((x, y)),
+        _ => Err(format!("bad coordinates: {}", s)),
+    }
+}
+
+/// Places `atom` at each cell in `places`, returning the first cell outside the grid.
+fn place<R: RngCore>(
+    ew: &mut SparseGrid<R>,
+    places: &[(usize, usize)],
+    atom: Const,
+) -> Result<(), (usize, usize)> {
+    for &(x, y) in places {
+        if !ew.set_cell(x, y, atom) {
+            return Err((x, y));
+        }
+    }
+    Ok(())
+}
+
+/// Places up to `count` copies of `atom` on random empty cells and returns the number placed.
+fn scatter<R: RngCore>(ew: &mut SparseGrid<R>, atom: Const, count: usize) -> usize {
+    let cells = ew.cells();
+    let mut placed = 0;
+    for _ in 0..count {
+        for _ in 0..MAX_SEED_TRIES {
+            let i = ew.rand_u32() as usize % cells;
+            if ew.try_place(i, atom) {
+                placed += 1;
+                break;
+            }
+        }
+    }
+    placed
+}
+
+/// Captures the grid paint as a single animation frame.
+fn paint_frame<T: Blit>(ew: &T, width: u32, height: u32, delay_ms: u32) -> Frame {
+    let mut im = RgbaImage::new(width, height);
+    ew.unblit_image(&mut im);
+    Frame::from_parts(im, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1))
+}
+
+/// Returns the terminal width from `$COLUMNS`, falling back to 80 columns.
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(80)
+}
+
+/// Renders `im` as truecolor half blocks, downsampled to fit within `max_cols` columns.
+/// Each terminal cell shows two sampled pixels, the upper in the foreground and the lower in
+/// the background, which keeps the preview roughly square.
+fn preview(im: &RgbaImage, max_cols: usize) -> String {
+    let (width, height) = im.dimensions();
+    let max_cols = max_cols.max(1) as u32;
+    let step = width.div_ceil(max_cols).max(1);
+    let paint = |x, y| Color::from(u32::from_be_bytes(im.get_pixel(x, y).0)).components();
+    let mut s = String::new();
+    for y in (0..height).step_by(2 * step as usize) {
+        for x in (0..width).step_by(step as usize) {
+            let (r, g, b, _) = paint(x, y);
+            let cell = "\u{2580}".truecolor(r, g, b);
+            if y + step < height {
+                let (r, g, b, _) = paint(x, y + step);
+                s.push_str(&cell.on_truecolor(r, g, b).to_string());
+            } else {
+                s.push_str(&cell.to_string());
+            }
+        }
+        s.push('\n');
+    }
+    s
+}
+
+/// Runs up to `--max-events` events over the input image and returns the number run. The run is
+/// fully determined by the inputs and `--random-seed`.
+fn ewimops_main(args: &Cli) -> u64 {
+    if args.frame_every == 0 {
+        eprintln!("--frame-every must be positive.");
+        exit(1);
+    }
     let mut runtime = Runtime::new();
     let image = ImageReader::open(Path::new::<String>(&args.input))
         .expect("Failed to open input image")
@@ -93,32 +285,290 @@ fn ewimops_main(args: &Cli) {
         File::open(Path::new::<String>(&args.init)).expect("Failed to open init file");
     let mut r = BufReader::new(&mut init_file);
     let init = runtime
-        .load_from_reader(&mut r)
-        .expect("Failed to process init file");
+        .load_all_from_reader(&mut r)
+        .expect("Failed to process init file")
+        .into_iter()
+        .next()
+        .expect("Init file has no elements");
     for op in &args.ops {
         let mut file = File::open(Path::new::<String>(op)).expect("Failed to open op file");
         let mut r = BufReader::new(&mut file);
         runtime
-            .load_from_reader(&mut r)
+            .load_all_from_reader(&mut r)
             .expect("Failed to process op file");
     }
-    let mut rng = SmallRng::from_entropy();
+    let mut rng = SmallRng::seed_from_u64(args.random_seed);
     let (width, height) = image.dimensions();
-    let mut ew = SparseGrid::new(&mut rng, (width as usize, height as usize));
+    let mut ew = match SparseGrid::new(&mut rng, (width as usize, height as usize)) {
+        Ok(ew) => ew,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
     ew.blit_image(&image.into_rgba8());
-    ew.set(0, init.new_atom());
-    let mut cursor = Cursor::with_symmetry(select_symmetries(ew.rand_u32(), init.symmetries));
-    for _ in 0..10000000 {
-        Runtime::execute(&mut ew, &mut cursor, &runtime.code_map).expect("Failed to execute");
+    if args.places.is_empty() {
+        ew.set_window(0, init.new_atom());
+    } else {
+        if let Err((x, y)) = place(&mut ew, &args.places, init.new_atom()) {
+            eprintln!("--place {},{} is outside the grid.", x, y);
+            exit(1);
+        }
         ew.reset();
-        cursor.reset(select_symmetries(ew.rand_u32(), init.symmetries));
+    }
+    for (name, count) in &args.seeds {
+        let elem = match runtime.type_map.values().find(|e| &e.name == name) {
+            Some(elem) => elem,
+            None => {
+                eprintln!("Unknown seed element: {}", name);
+                exit(1);
+            }
+        };
+        let placed = scatter(&mut ew, elem.new_atom(), *count);
+        if placed < *count {
+            warn!("Placed {} of {} {} atoms", placed, count, name);
+        }
+    }
+    let mut cursor = Cursor::new();
+    runtime.reset_cursor(&mut ew, &mut cursor);
+    let mut gif = args.gif.as_ref().map(|gif| {
+        let file = fs::File::create(Path::new::<String>(gif)).expect("Failed to create GIF file");
+        let mut encoder = GifEncoder::new(file);
+        encoder
+            .set_repeat(match args.gif_loops {
+                0 => Repeat::Infinite,
+                n => Repeat::Finite(n),
+            })
+            .expect("Failed to configure GIF");
+        encoder
+    });
+    let interval = if let Some(encoder) = gif.as_mut() {
+        encoder
+            .encode_frame(paint_frame(&ew, width, height, args.frame_delay))
+            .expect("Failed to write GIF frame");
+        gcd(args.frame_every, args.progress_every)
+    } else {
+        args.progress_every
+    };
+    let stats = runtime.run(
+        &mut ew,
+        &mut cursor,
+        args.max_events,
+        interval,
+        args.quiescence_window,
+        |ew, p| {
+            if let Some(encoder) = gif.as_mut() {
+                if p.events < p.total && p.events.is_multiple_of(args.frame_every) {
+                    encoder
+                        .encode_frame(paint_frame(ew, width, height, args.frame_delay))
+                        .expect("Failed to write GIF frame");
+                }
+            }
+            if !args.quiet
+                && args.progress_every > 0
+                && p.events.is_multiple_of(args.progress_every)
+            {
+                eprintln!(
+                    "{}/{} events, population {}",
+                    p.events, p.total, p.population
+                );
+            }
+        },
+    );
+    if let Some(e) = stats.error {
+        panic!("Failed to execute: {:?}", e);
+    }
+    if stats.quiescent && !args.quiet {
+        eprintln!("Quiescent after {} events", stats.events);
+    }
+    if let Some(encoder) = gif.as_mut() {
+        encoder
+            .encode_frame(paint_frame(&ew, width, height, args.frame_delay))
+            .expect("Failed to write GIF frame");
+    }
+    let mut im = DynamicImage::new_rgba8(width, height);
+    ew.unblit_image(im.as_mut_rgba8().unwrap());
+    if args.preview {
+        let cols = args.preview_width.unwrap_or_else(terminal_width);
+        print!("{}", preview(im.as_rgba8().unwrap(), cols));
     }
     if let Some(output) = &args.output {
-        let mut im = DynamicImage::new_rgba8(width, height);
-        ew.unblit_image(im.as_mut_rgba8().unwrap());
         let mut file = fs::File::create(Path::new::<String>(output))
             .expect("Failed to create output image file");
         im.write_to(&mut file, image::ImageOutputFormat::Png)
             .expect("Failed to write output image");
     }
+    stats.events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::Includes;
+    use image::gif::GifDecoder;
+    use image::AnimationDecoder;
+    use rand::rngs::mock::StepRng;
+
+    #[test]
+    fn test_parse_seed() {
+        assert_eq!(parse_seed("Res=10"), Ok(("Res".to_owned(), 10)));
+        assert!(parse_seed("Res").is_err());
+        assert!(parse_seed("Res=x").is_err());
+    }
+
+    #[test]
+    fn test_place() {
+        assert_eq!(parse_point("3,4"), Ok((3, 4)));
+        assert!(parse_point("3").is_err());
+        let mut rng = StepRng::new(0, 1);
+        let mut ew = SparseGrid::new(&mut rng, (4, 4)).unwrap();
+        let atom = Const::from(1u8);
+        place(&mut ew, &[(1, 2), (3, 0)], atom).unwrap();
+        assert_eq!(ew.get_cell(1, 2), atom);
+        assert_eq!(ew.get_cell(3, 0), atom);
+        assert_eq!(ew.population(), 2);
+        assert_eq!(place(&mut ew, &[(4, 0)], atom), Err((4, 0)));
+    }
+
+    #[test]
+    fn test_scatter() {
+        let mut rng = SmallRng::seed_from_u64(1337);
+        let mut ew = SparseGrid::new(&mut rng, (2, 2)).unwrap();
+        let atom = Const::from(1u8);
+        assert_eq!(scatter(&mut ew, atom, 3), 3);
+        assert_eq!(scatter(&mut ew, atom, 3), 1);
+        assert_eq!(ew.population(), 4);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let dir = std::env::temp_dir().join(format!("ewimops-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.png");
+        RgbaImage::from_pixel(8, 8, [0, 0, 0, 0xff].into())
+            .save(&input)
+            .unwrap();
+        let init = dir.join("init.bin");
+        let mut bin = Vec::new();
+        code::Compiler::new("test")
+            .compile_to_writer(
+                &mut bin,
+                ".name \"W\"\n.symmetries ALL\n.radius 1\n  rand\n  setpaint\n  push1\n  push0\n  swapsites\n",
+                &mut Includes::new(),
+            )
+            .unwrap();
+        fs::write(&init, bin).unwrap();
+        let run = |name: &str| {
+            let output = dir.join(name);
+            let args = Cli::from_iter(&[
+                "ewimops",
+                input.to_str().unwrap(),
+                "--init",
+                init.to_str().unwrap(),
+                "--output",
+                output.to_str().unwrap(),
+                "--random-seed",
+                "7",
+                "--max-events",
+                "1000",
+                "--quiet",
+            ]);
+            assert_eq!(ewimops_main(&args), 1000);
+            fs::read(output).unwrap()
+        };
+        let a = run("a.png");
+        let b = run("b.png");
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(a == b);
+    }
+
+    #[test]
+    fn test_quiescence() {
+        let dir = std::env::temp_dir().join(format!("ewimops-quiet-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.png");
+        RgbaImage::new(4, 4).save(&input).unwrap();
+        let init = dir.join("init.bin");
+        let mut bin = Vec::new();
+        code::Compiler::new("test")
+            .compile_to_writer(&mut bin, ".name \"X\"\n  exit\n", &mut Includes::new())
+            .unwrap();
+        fs::write(&init, bin).unwrap();
+        let args = Cli::from_iter(&[
+            "ewimops",
+            input.to_str().unwrap(),
+            "--init",
+            init.to_str().unwrap(),
+            "--max-events",
+            "1000000",
+            "--quiescence-window",
+            "50",
+            "--quiet",
+        ]);
+        let events = ewimops_main(&args);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(events, 50);
+    }
+
+    #[test]
+    fn test_preview() {
+        colored::control::set_override(true);
+        let mut im = RgbaImage::new(3, 2);
+        im.put_pixel(0, 0, [0xff, 0, 0, 0xff].into());
+        im.put_pixel(0, 1, [0, 0xff, 0, 0xff].into());
+        im.put_pixel(2, 0, [0, 0, 0xff, 0xff].into());
+        let cell = |fg: (u8, u8, u8), bg: (u8, u8, u8)| {
+            "\u{2580}"
+                .truecolor(fg.0, fg.1, fg.2)
+                .on_truecolor(bg.0, bg.1, bg.2)
+                .to_string()
+        };
+        let black = (0, 0, 0);
+        assert_eq!(
+            preview(&im, 80),
+            [
+                cell((0xff, 0, 0), (0, 0xff, 0)),
+                cell(black, black),
+                cell((0, 0, 0xff), black),
+                "\n".to_owned(),
+            ]
+            .concat()
+        );
+        // Downsampled to two columns, sampling every other pixel and a single row.
+        assert_eq!(
+            preview(&im, 2),
+            [
+                "\u{2580}".truecolor(0xff, 0, 0).to_string(),
+                "\u{2580}".truecolor(0, 0, 0xff).to_string(),
+                "\n".to_owned(),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_gif_frames() {
+        let mut rng = StepRng::new(0, 1);
+        let mut ew = SparseGrid::new(&mut rng, (2, 2)).unwrap();
+        let mut v = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut v);
+            encoder.set_repeat(Repeat::Finite(2)).unwrap();
+            for c in &[0xff0000ffu32, 0x00ff00ff, 0x0000ffff] {
+                let mut im = RgbaImage::new(2, 2);
+                for p in im.pixels_mut() {
+                    *p = c.to_be_bytes().into();
+                }
+                ew.blit_image(&im);
+                encoder.encode_frame(paint_frame(&ew, 2, 2, 50)).unwrap();
+            }
+        }
+        let frames = GifDecoder::new(v.as_slice())
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].buffer().get_pixel(1, 1).0, [0, 0xff, 0, 0xff]);
+    }
 }
@@ -0,0 +1,205 @@
+#[path = "../runtime/mod.rs"]
+mod runtime;
+
+#[path = "../base/mod.rs"]
+mod base;
+
+#[path = "../ast.rs"]
+mod ast;
+
+#[path = "../code.rs"]
+mod code;
+
+use crate::base::arith::Const;
+use crate::code::{Compiler, Includes};
+use crate::runtime::mfm::{
+    debug_event_window, select_symmetries, EventWindow, MinimalEventWindow, Rand,
+};
+use crate::runtime::{Cursor, Runtime};
+use atty::Stream;
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use std::io;
+use std::io::prelude::*;
+use stderrlog;
+use structopt::StructOpt;
+use thiserror;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "ewrepl",
+    about = "Interactively build and run an EWAL element against a single event window."
+)]
+struct Cli {
+    #[structopt(
+        long = "random-seed",
+        help = "A 64 bit seed used to initialize the random number generator before each run.",
+        default_value = "1337"
+    )]
+    random_seed: u64,
+
+    #[structopt(short = "q", long = "quiet", help = "Silence all logging output.")]
+    quiet: bool,
+
+    #[structopt(
+        short = "v",
+        long = "verbose",
+        help = "Configure logging verbosity",
+        parse(from_occurrences)
+    )]
+    verbose: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ReplError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("compile error: {0}")]
+    Compile(String),
+    #[error("runtime error: {0}")]
+    Runtime(#[from] runtime::Error),
+}
+
+/// Repl accumulates EWAL source lines into a single element.
+///
+/// Metadata lines (starting with `.`) are collected into the header and all other lines into the
+/// body. After each line the whole element is recompiled and run once from a fresh event window.
+/// Lines which fail to compile or run are discarded, so a jump or call must name a label which
+/// has already been entered.
+struct Repl {
+    header: Vec<String>,
+    body: Vec<String>,
+    random_seed: u64,
+}
+
+impl Repl {
+    fn new(random_seed: u64) -> Self {
+        Self {
+            header: vec![".name \"Repl\"".to_owned()],
+            body: Vec::new(),
+            random_seed,
+        }
+    }
+
+    fn source(&self) -> String {
+        let mut s = String::new();
+        for line in self.header.iter().chain(self.body.iter()) {
+            s.push_str(line);
+            s.push('\n');
+        }
+        s
+    }
+
+    /// Submits a line, renders the resulting event window to `w` and returns the window sites.
+    fn eval<W: Write>(&mut self, line: &str, w: &mut W) -> Result<Vec<Const>, ReplError> {
+        let line = line.trim();
+        let lines = if line.starts_with('.') {
+            &mut self.header
+        } else {
+            &mut self.body
+        };
+        lines.push(line.to_owned());
+        let res = self.run(w);
+        if res.is_err() {
+            if line.starts_with('.') {
+                self.header.pop();
+            } else {
+                self.body.pop();
+            }
+        }
+        res
+    }
+
+    fn run<W: Write>(&self, w: &mut W) -> Result<Vec<Const>, ReplError> {
+        let src = self.source();
+        let mut bin = Vec::new();
+        Compiler::new("repl")
+            .compile_to_writer(&mut bin, src.as_str(), &mut Includes::new())
+            .map_err(|e| ReplError::Compile(e.to_string()))?;
+
+        let mut runtime = Runtime::new();
+        let elem = runtime.load_from_reader(&mut bin.as_slice())?;
+        let mut rng = SmallRng::seed_from_u64(self.random_seed);
+        let mut ew = MinimalEventWindow::new(&mut rng);
+        let mut cursor = Cursor::with_symmetry(select_symmetries(ew.rand_u32(), elem.symmetries));
+        ew.set(0, elem.new_atom());
+        Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map)?;
+        debug_event_window(&ew, w, &runtime.type_map)?;
+        Ok((0..41).map(|i| ew.get(i)).collect())
+    }
+}
+
+fn main() {
+    let args = Cli::from_args();
+    stderrlog::new()
+        .quiet(args.quiet)
+        .verbosity(args.verbose)
+        .init()
+        .unwrap();
+    ewrepl_main(&args);
+}
+
+fn ewrepl_main(args: &Cli) {
+    let mut repl = Repl::new(args.random_seed);
+    let interactive = atty::is(Stream::Stdin);
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    loop {
+        if interactive {
+            print!("> ");
+            stdout.flush().expect("Failed to flush stdout");
+        }
+        let mut line = String::new();
+        if stdin
+            .lock()
+            .read_line(&mut line)
+            .expect("Failed to read stdin")
+            == 0
+        {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Err(e) = repl.eval(&line, &mut stdout) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::FieldSelector;
+
+    #[test]
+    fn test_eval() {
+        let mut repl = Repl::new(1337);
+        let mut out = Vec::new();
+        for line in &[
+            ".symbol \"R\"",
+            ".radius 1",
+            "push1",
+            "push0",
+            "getsite",
+            "setsite",
+        ] {
+            repl.eval(line, &mut out).unwrap();
+        }
+        assert!(repl.eval("bad_command", &mut out).is_err());
+        assert!(matches!(
+            repl.eval("jump end", &mut out),
+            Err(ReplError::Compile(_))
+        ));
+        assert!(matches!(
+            repl.eval("pop", &mut out),
+            Err(ReplError::Runtime(_))
+        ));
+        let sites = repl.eval("nop", &mut out).unwrap();
+        assert_eq!(sites[1], sites[0]);
+        assert_eq!(sites[0].apply(&FieldSelector::TYPE), Const::Unsigned(1));
+        assert!(sites[2..].iter().all(|x| x.is_zero()));
+        assert_eq!(repl.body.len(), 5);
+        assert_eq!(repl.header.len(), 3);
+    }
+}
@@ -1,56 +1,130 @@
-use crate::ast::{Instruction, Metadata, Node};
+use crate::ast;
+use crate::ast::{File, Instruction, Metadata, Node};
 use crate::base;
 use crate::base::arith::Const;
 use crate::base::color::{Color, ParseColorError};
 use byteorder::BigEndian;
 use byteorder::WriteBytesExt;
+use indexmap::IndexMap;
 use lalrpop_util;
 use lalrpop_util::lalrpop_mod;
-use log::trace;
-use std::collections::HashMap;
+use log::{trace, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 use std::io;
+use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use thiserror;
 
 #[ignore = "unused"]
 lalrpop_mod!(pub substrate); // syntesized by LALRPOP
 
+type ParseError<'input> =
+    lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, SyntaxError>;
+
+/// Errors raised by grammar actions, as opposed to the parser itself.
+#[derive(thiserror::Error, Clone, Debug, PartialEq)]
+pub enum SyntaxError {
+    #[error("{0}")]
+    OutOfRange(&'static str),
+    /// A number literal which does not fit its type, with the byte span of the literal.
+    #[error("bad number literal at {1}..{2}: {0}")]
+    ParseIntError(ParseIntError, usize, usize),
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum CompileError<'input> {
     #[error("IO error")]
     IOError(#[from] io::Error),
     #[error("parse error")]
-    ParseError(lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, &'input str>),
+    ParseError(ParseError<'input>),
+    #[error("bad number literal at {start}..{end}: {error}")]
+    ParseIntError {
+        error: ParseIntError,
+        start: usize,
+        end: usize,
+    },
     #[error("parse color error")]
     ParseColorError(#[from] ParseColorError),
     #[error("unexpected node type")]
     InternalUnexpectedNodeType,
     #[error("element is missing a name")]
-    NoName,
+    MissingName,
     #[error("max code size reached: branches are unstable")]
     MaxCodeSize,
+    #[error("too many metadata entries: {0}, at most 255")]
+    MaxHeaderSize(usize),
+    #[error("undefined label: {0}")]
+    UndefinedLabel(&'input str),
+    #[error("undefined field: {0}")]
+    UndefinedField(&'input str),
+    #[error("field {0} {1} runs past the {} bit atom", ATOM_BITS)]
+    FieldOutOfRange(&'input str, base::FieldSelector),
+    #[error("unknown type: {0}")]
+    UnknownType(&'input str),
+    #[error("undefined parameter: {0}")]
+    UndefinedParameter(&'input str),
+    #[error("undefined base element: {0}")]
+    UndefinedBase(&'input str),
+    #[error("extends cycle: {0}")]
+    ExtendsCycle(String),
+    #[error("labels have the same hash: {0} and {1}")]
+    LabelHashCollision(&'input str, &'input str),
+    #[error("conflicting definitions of: {0}")]
+    DuplicateDefinition(&'input str),
+    #[error("include cycle: {0}")]
+    IncludeCycle(String),
+    #[error("stack imbalance at instruction {ip}: depth {depth}")]
+    StackImbalance { ip: u16, depth: usize },
 }
 
-impl<'input> From<lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, &'input str>>
-    for CompileError<'input>
-{
-    fn from(
-        x: lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, &'input str>,
-    ) -> Self {
-        CompileError::ParseError(x)
+impl<'input> From<ParseError<'input>> for CompileError<'input> {
+    fn from(x: ParseError<'input>) -> Self {
+        match x {
+            lalrpop_util::ParseError::User {
+                error: SyntaxError::ParseIntError(error, start, end),
+            } => CompileError::ParseIntError { error, start, end },
+            x => CompileError::ParseError(x),
+        }
     }
 }
 
 const MAGIC_NUMBER: u32 = 0x02030741;
+/// Fields must lie within the low bits of an atom which hold its data and header.
+const ATOM_BITS: u32 = 96;
 
 pub struct Compiler {
     build_tag: String,
     self_name: String,
     type_map: HashMap<String, u16>,
+    /// Omit `.desc`, `.author` and `.license`, which do not affect how an element runs.
+    pub strip_docs: bool,
+    /// Reject elements whose stack depth can be shown to go wrong (see `ast::check_stack_depth`,
+    /// which is run with `balanced` set).
+    pub verify_stack: bool,
+    /// Directory `.include` paths in the compiled source are relative to. Defaults to the current
+    /// directory. Paths in included files are relative to the including file.
+    pub include_dir: Option<PathBuf>,
+}
+
+/// Source files read by `.include`, by canonical path.
+///
+/// The caller owns them so that the declarations they hold may be borrowed for as long as the
+/// source which includes them. One `Includes` may be shared by several compilations.
+#[derive(Default)]
+pub struct Includes {
+    sources: HashMap<PathBuf, String>,
+}
+
+impl Includes {
+    pub fn new() -> Self {
+        Self::default()
+    }
 }
 
 impl Compiler {
-    const MINOR_VERSION: u16 = 1;
+    const MINOR_VERSION: u16 = 4;
     const MAJOR_VERSION: u16 = 0;
     const MAX_CODE_SIZE: usize = (u16::MAX - 1) as usize;
 
@@ -59,6 +133,9 @@ impl Compiler {
             build_tag: build_tag.to_owned(),
             self_name: String::new(),
             type_map: Self::new_type_map(),
+            strip_docs: false,
+            verify_stack: false,
+            include_dir: None,
         }
     }
 
@@ -79,7 +156,7 @@ impl Compiler {
     fn index_metadata_node<'input>(
         n: Node<'input>,
         type_map: &mut HashMap<String, u16>,
-        const_map: &mut HashMap<&'input str, Const>,
+        const_map: &mut IndexMap<&'input str, Const>,
         field_map: &mut HashMap<&'input str, base::FieldSelector>,
         self_name: &mut String,
     ) -> Result<(), CompileError<'input>> {
@@ -88,12 +165,18 @@ impl Compiler {
                 Metadata::Name(i) => {
                     let n = type_map.len();
                     *self_name = i.to_owned();
-                    type_map.insert(self_name.to_owned(), n as u16);
+                    type_map.entry(self_name.to_owned()).or_insert(n as u16);
                 }
                 Metadata::Parameter(i, c) => {
                     const_map.insert(i, c);
                 }
                 Metadata::Field(i, f) => {
+                    if f.offset as u32 + f.length as u32 > ATOM_BITS {
+                        return Err(CompileError::FieldOutOfRange(i, f));
+                    }
+                    if f.mask() & base::FieldSelector::HEADER.mask() != 0 {
+                        warn!("field {} {} overlaps the atom header", i, f);
+                    }
                     field_map.insert(i, f);
                 }
                 _ => {}
@@ -112,12 +195,31 @@ impl Compiler {
             Node::Label(i) => {
                 label_map.insert(i, *ln);
             }
-            Node::Instruction(_) => *ln += 1,
+            Node::Instruction(_) => {
+                if *ln as usize >= Self::MAX_CODE_SIZE {
+                    return Err(CompileError::MaxCodeSize);
+                }
+                *ln += 1
+            }
             _ => return Err(CompileError::InternalUnexpectedNodeType),
         }
         Ok(())
     }
 
+    /// Returns the code index `i` may branch or call to.
+    fn branch_target<'input>(
+        i: &Instruction<'input>,
+        label_map: &HashMap<&'input str, u16>,
+    ) -> Result<Option<u16>, CompileError<'input>> {
+        match i {
+            Instruction::Jump(x)
+            | Instruction::JumpZero(x)
+            | Instruction::JumpNonZero(x)
+            | Instruction::Call(x) => Self::label(label_map, x.ast()).map(Some),
+            _ => Ok(None),
+        }
+    }
+
     /// write the type and value of a U96 constant.
     fn write_u96<W: WriteBytesExt>(w: &mut W, x: Const) -> Result<(), io::Error> {
         match x {
@@ -128,9 +230,64 @@ impl Compiler {
         w.write_u64::<BigEndian>(x.into())
     }
 
+    /// Writes `x` under the narrowest type tag which holds it. Tags 0 and 1 are the full 96 bit
+    /// unsigned and signed encodings, 2 to 4 are `u8`, `u16` and `u32`, and 5 to 7 are `i8`,
+    /// `i16` and `i32`.
+    fn write_const<W: WriteBytesExt>(w: &mut W, x: Const) -> Result<(), io::Error> {
+        match x {
+            Const::Unsigned(v) => {
+                if let Ok(v) = u8::try_from(v) {
+                    w.write_u8(2)?;
+                    w.write_u8(v)
+                } else if let Ok(v) = u16::try_from(v) {
+                    w.write_u8(3)?;
+                    w.write_u16::<BigEndian>(v)
+                } else if let Ok(v) = u32::try_from(v) {
+                    w.write_u8(4)?;
+                    w.write_u32::<BigEndian>(v)
+                } else {
+                    Self::write_u96(w, x)
+                }
+            }
+            Const::Signed(v) => {
+                if let Ok(v) = i8::try_from(v) {
+                    w.write_u8(5)?;
+                    w.write_i8(v)
+                } else if let Ok(v) = i16::try_from(v) {
+                    w.write_u8(6)?;
+                    w.write_i16::<BigEndian>(v)
+                } else if let Ok(v) = i32::try_from(v) {
+                    w.write_u8(7)?;
+                    w.write_i32::<BigEndian>(v)
+                } else {
+                    Self::write_u96(w, x)
+                }
+            }
+        }
+    }
+
+    /// Writes the label offsets as `(label_hash, offset)` pairs ordered by hash.
+    fn write_code_index<'input, W: WriteBytesExt>(
+        w: &mut W,
+        label_map: &HashMap<&'input str, u16>,
+    ) -> Result<(), CompileError<'input>> {
+        let mut index = BTreeMap::new();
+        for (name, offset) in label_map.iter() {
+            if let Some((other, _)) = index.insert(base::name_hash(name), (*name, *offset)) {
+                return Err(CompileError::LabelHashCollision(other, name));
+            }
+        }
+        w.write_u16::<BigEndian>(index.len() as u16)?;
+        for (hash, (_, offset)) in index.iter() {
+            w.write_u32::<BigEndian>(*hash)?;
+            w.write_u16::<BigEndian>(*offset)?;
+        }
+        Ok(())
+    }
+
     fn write_string<'input, W: WriteBytesExt>(
         w: &mut W,
-        x: &'input str,
+        x: &str,
     ) -> Result<(), CompileError<'input>> {
         let data = x.as_bytes();
         w.write_u8(data.len() as u8)?;
@@ -167,17 +324,52 @@ impl Compiler {
             }
             Metadata::Parameter(i, c) => {
                 Self::write_string(w, i)?;
-                Self::write_u96(w, c).map_err(|x| x.into())
+                Self::write_const(w, c).map_err(|x| x.into())
+            }
+            Metadata::Wrapping(x) => w.write_u8(x as u8).map_err(|x| x.into()),
+            // Resolved by the compiler and never written.
+            Metadata::Extends(_) | Metadata::Include(_) => {
+                Err(CompileError::InternalUnexpectedNodeType)
             }
         }
     }
 
+    fn field<'input>(
+        field_map: &HashMap<&'input str, base::FieldSelector>,
+        name: &'input str,
+    ) -> Result<base::FieldSelector, CompileError<'input>> {
+        field_map
+            .get(name)
+            .copied()
+            .ok_or(CompileError::UndefinedField(name))
+    }
+
+    fn label<'input>(
+        label_map: &HashMap<&'input str, u16>,
+        name: &'input str,
+    ) -> Result<u16, CompileError<'input>> {
+        label_map
+            .get(name)
+            .copied()
+            .ok_or(CompileError::UndefinedLabel(name))
+    }
+
+    fn type_num<'input>(
+        type_map: &HashMap<String, u16>,
+        name: &'input str,
+    ) -> Result<u16, CompileError<'input>> {
+        type_map
+            .get(name)
+            .copied()
+            .ok_or(CompileError::UnknownType(name))
+    }
+
     fn write_instruction<'input, W: WriteBytesExt>(
         w: &mut W,
         n: Node<'input>,
         type_map: &HashMap<String, u16>,
         label_map: &HashMap<&'input str, u16>,
-        const_map: &HashMap<&'input str, Const>,
+        const_map: &IndexMap<&'input str, Const>,
         field_map: &HashMap<&'input str, base::FieldSelector>,
     ) -> Result<(), CompileError<'input>> {
         let i = match n {
@@ -191,17 +383,32 @@ impl Compiler {
             Instruction::Exit => Ok(()),
             Instruction::SwapSites => Ok(()),
             Instruction::SetSite => Ok(()),
-            Instruction::SetField(x) => w.write_u16::<BigEndian>(field_map[x.ast()].into()),
-            Instruction::SetSiteField(x) => w.write_u16::<BigEndian>(field_map[x.ast()].into()),
+            Instruction::SetField(x) => {
+                w.write_u16::<BigEndian>(Self::field(field_map, x.ast())?.into())
+            }
+            Instruction::SetSiteField(x) => {
+                w.write_u16::<BigEndian>(Self::field(field_map, x.ast())?.into())
+            }
             Instruction::GetSite => Ok(()),
-            Instruction::GetField(x) => w.write_u16::<BigEndian>(field_map[x.ast()].into()),
-            Instruction::GetSiteField(x) => w.write_u16::<BigEndian>(field_map[x.ast()].into()),
-            Instruction::GetSignedField(x) => w.write_u16::<BigEndian>(field_map[x.ast()].into()),
+            Instruction::GetField(x) => {
+                w.write_u16::<BigEndian>(Self::field(field_map, x.ast())?.into())
+            }
+            Instruction::GetSiteField(x) => {
+                w.write_u16::<BigEndian>(Self::field(field_map, x.ast())?.into())
+            }
+            Instruction::GetSignedField(x) => {
+                w.write_u16::<BigEndian>(Self::field(field_map, x.ast())?.into())
+            }
             Instruction::GetSignedSiteField(x) => {
-                w.write_u16::<BigEndian>(field_map[x.ast()].into())
+                w.write_u16::<BigEndian>(Self::field(field_map, x.ast())?.into())
+            }
+            Instruction::GetType(x) => w.write_u16::<BigEndian>(Self::type_num(type_map, x.ast())?),
+            Instruction::GetParameter(x) => {
+                let c = const_map
+                    .get(x.ast())
+                    .ok_or(CompileError::UndefinedParameter(x.ast()))?;
+                Self::write_const(w, *c)
             }
-            Instruction::GetType(x) => w.write_u16::<BigEndian>(type_map[x.ast().to_owned()]),
-            Instruction::GetParameter(x) => Self::write_u96(w, const_map[x.ast()]),
             Instruction::Scan => Ok(()),
             Instruction::SaveSymmetries => Ok(()),
             Instruction::UseSymmetries(x) => w.write_u8(x.bits() as u8),
@@ -247,10 +454,10 @@ impl Compiler {
             | Instruction::Push38
             | Instruction::Push39
             | Instruction::Push40 => Ok(()),
-            Instruction::Push(x) => Self::write_u96(w, x),
+            Instruction::Push(x) => Self::write_const(w, x),
             Instruction::Pop | Instruction::Dup | Instruction::Over | Instruction::Swap => Ok(()),
             Instruction::Rot => Ok(()),
-            Instruction::Call(x) => w.write_u16::<BigEndian>(label_map[x.ast()]),
+            Instruction::Call(x) => w.write_u16::<BigEndian>(Self::label(label_map, x.ast())?),
             Instruction::Ret => Ok(()),
             Instruction::Checksum => Ok(()),
             Instruction::Add
@@ -270,33 +477,329 @@ impl Compiler {
             | Instruction::BitScanReverse
             | Instruction::LShift
             | Instruction::RShift => Ok(()),
-            Instruction::Jump(x) => w.write_u16::<BigEndian>(label_map[x.ast()]),
+            Instruction::Jump(x) => w.write_u16::<BigEndian>(Self::label(label_map, x.ast())?),
             Instruction::JumpRelativeOffset => Ok(()),
-            Instruction::JumpZero(x) => w.write_u16::<BigEndian>(label_map[x.ast()]),
-            Instruction::JumpNonZero(x) => w.write_u16::<BigEndian>(label_map[x.ast()]),
+            Instruction::JumpZero(x) => w.write_u16::<BigEndian>(Self::label(label_map, x.ast())?),
+            Instruction::JumpNonZero(x) => {
+                w.write_u16::<BigEndian>(Self::label(label_map, x.ast())?)
+            }
             Instruction::SetPaint | Instruction::GetPaint => Ok(()),
-            Instruction::Rand => Ok(()),
+            Instruction::SetSitePaint | Instruction::GetSitePaint => Ok(()),
+            Instruction::CountType => Ok(()),
+            Instruction::Rand | Instruction::RandRange => Ok(()),
+            Instruction::AtomEqual | Instruction::Compare => Ok(()),
+            Instruction::IsEmpty => Ok(()),
+            Instruction::Begin | Instruction::Commit | Instruction::Abort => Ok(()),
+            Instruction::SiteToXY | Instruction::XYToSite => Ok(()),
+            Instruction::ModEuclid => Ok(()),
+            // The parameter belongs to whichever element is named at runtime, so it is written by
+            // name and not checked here.
+            Instruction::GetParameterOf(x) => w.write_u32::<BigEndian>(base::name_hash(x.ast())),
+            Instruction::SetSignedField(x) => {
+                w.write_u16::<BigEndian>(Self::field(field_map, x.ast())?.into())
+            }
+            Instruction::SetSignedSiteField(x) => {
+                w.write_u16::<BigEndian>(Self::field(field_map, x.ast())?.into())
+            }
+            Instruction::NeighborCount(x) | Instruction::SiteIs(x) => {
+                w.write_u16::<BigEndian>(Self::type_num(type_map, x.ast())?)
+            }
+            Instruction::GetParameterIndex(x) => {
+                // Parameters are written to the header in declaration order, which the runtime
+                // preserves, so the position here is also the runtime index.
+                let i = const_map
+                    .get_index_of(x.ast())
+                    .ok_or(CompileError::UndefinedParameter(x.ast()))?;
+                w.write_u16::<BigEndian>(i as u16)
+            }
         }
         .map_err(|x| x.into())
     }
 
+    /// Compiles each element in `src` in turn, writing them one after another to `w`.
+    ///
+    /// Elements are numbered before any are compiled, so that they may refer to elements defined
+    /// later in the file. Files read by `.include` are kept in `includes`.
     pub fn compile_to_writer<'input, W: WriteBytesExt>(
         &'input mut self,
         w: &mut W,
         src: &'input str,
+        includes: &'input mut Includes,
     ) -> Result<(), CompileError<'input>> {
-        let ast = substrate::FileParser::new().parse(src)?;
-        trace!("{:?}", ast);
+        let mut files = substrate::FileParser::new().parse(src)?;
+        for f in files.iter() {
+            let paths: Vec<String> = Self::include_paths(&f.header).map(str::to_owned).collect();
+            self.read_includes(&paths, self.include_dir.clone(), includes, &mut Vec::new())?;
+        }
+        let includes: &'input Includes = includes;
+        self.declare_files(&files);
+        for f in files.iter_mut() {
+            let mut included = Vec::new();
+            let dir = self.include_dir.clone();
+            self.include(&f.header, dir, includes, &mut Vec::new(), &mut included)?;
+            for n in f.header.iter() {
+                if let Some(m) = included.iter().find(|m| Self::conflicts(m, n)) {
+                    return Err(CompileError::DuplicateDefinition(
+                        Self::declared_name(m).unwrap(),
+                    ));
+                }
+            }
+            f.header.splice(0..0, included);
+        }
+        for f in files.iter() {
+            let bases = Self::bases(&files, f)?;
+            self.compile_element(w, f, &bases)?;
+        }
+        Ok(())
+    }
+
+    /// Gives type numbers to the elements of `src` without compiling them, so that sources
+    /// compiled earlier may refer to them by name. Elements keep their numbers when compiled.
+    pub fn declare_types<'input>(&mut self, src: &'input str) -> Result<(), CompileError<'input>> {
+        let files = substrate::FileParser::new().parse(src)?;
+        self.declare_files(&files);
+        Ok(())
+    }
+
+    fn declare_files(&mut self, files: &[File<'_>]) {
+        for f in files.iter() {
+            for n in f.header.iter() {
+                if let Node::Metadata(Metadata::Name(i)) = n {
+                    let k = self.type_map.len() as u16;
+                    self.type_map.entry(i.to_string()).or_insert(k);
+                }
+            }
+        }
+    }
+
+    /// Returns the paths of the `.include` directives in `header`.
+    fn include_paths<'a>(header: &'a [Node<'_>]) -> impl Iterator<Item = &'a str> {
+        header.iter().filter_map(|n| match n {
+            Node::Metadata(Metadata::Include(path)) => Some(*path),
+            _ => None,
+        })
+    }
+
+    /// Returns the canonical path of `path` included from a file in `dir`, or from the current
+    /// directory if there is none.
+    fn resolve_include(dir: &Option<PathBuf>, path: &str) -> Result<PathBuf, io::Error> {
+        match dir {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+        .canonicalize()
+    }
+
+    /// Reads each of `paths` included from a file in `dir`, and the files they include in turn,
+    /// into `includes`. `stack` holds the files being read with their paths as written, to catch
+    /// cycles.
+    fn read_includes<'input>(
+        &self,
+        paths: &[String],
+        dir: Option<PathBuf>,
+        includes: &mut Includes,
+        stack: &mut Vec<(PathBuf, String)>,
+    ) -> Result<(), CompileError<'input>> {
+        for path in paths.iter() {
+            let full = Self::resolve_include(&dir, path)?;
+            if stack.iter().any(|(p, _)| *p == full) {
+                let cycle: Vec<&str> = stack
+                    .iter()
+                    .map(|(_, name)| name.as_str())
+                    .chain(std::iter::once(path.as_str()))
+                    .collect();
+                return Err(CompileError::IncludeCycle(cycle.join(" -> ")));
+            }
+            if includes.sources.contains_key(&full) {
+                continue;
+            }
+            let src = std::fs::read_to_string(&full)?;
+            // Syntax errors are reported when the file is parsed again to be declared.
+            let nested: Vec<String> = match substrate::FileParser::new().parse(&src) {
+                Ok(files) => files
+                    .iter()
+                    .flat_map(|f| Self::include_paths(&f.header))
+                    .map(str::to_owned)
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            let parent = full.parent().map(Path::to_path_buf);
+            includes.sources.insert(full.clone(), src);
+            stack.push((full, path.clone()));
+            self.read_includes(&nested, parent, includes, stack)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+
+    /// Appends the fields and parameters declared by each `.include` in `header`, read from a
+    /// file in `dir`, to `out`, and gives the elements named in the included files type numbers.
+    /// `seen` holds every file included so far.
+    fn include<'input>(
+        &mut self,
+        header: &[Node<'input>],
+        dir: Option<PathBuf>,
+        includes: &'input Includes,
+        seen: &mut Vec<PathBuf>,
+        out: &mut Vec<Node<'input>>,
+    ) -> Result<(), CompileError<'input>> {
+        for path in Self::include_paths(header) {
+            let full = Self::resolve_include(&dir, path)?;
+            if seen.contains(&full) {
+                continue;
+            }
+            let src = includes.sources[&full].as_str();
+            let parent = full.parent().map(Path::to_path_buf);
+            seen.push(full);
+            for f in substrate::FileParser::new().parse(src)? {
+                self.include(&f.header, parent.clone(), includes, seen, out)?;
+                for n in f.header.iter() {
+                    match n {
+                        Node::Metadata(Metadata::Name(i)) => {
+                            let k = self.type_map.len() as u16;
+                            self.type_map.entry(i.to_string()).or_insert(k);
+                        }
+                        Node::Metadata(Metadata::Field(..))
+                        | Node::Metadata(Metadata::Parameter(..)) => {
+                            if let Some(m) = out.iter().find(|m| Self::conflicts(m, n)) {
+                                return Err(CompileError::DuplicateDefinition(
+                                    Self::declared_name(m).unwrap(),
+                                ));
+                            }
+                            out.push(*n);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the field or parameter `n` declares.
+    fn declared_name<'input>(n: &Node<'input>) -> Option<&'input str> {
+        match n {
+            Node::Metadata(Metadata::Field(i, _)) | Node::Metadata(Metadata::Parameter(i, _)) => {
+                Some(*i)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `a` and `b` declare the same field or parameter differently.
+    fn conflicts(a: &Node<'_>, b: &Node<'_>) -> bool {
+        match (a, b) {
+            (Node::Metadata(Metadata::Field(i, x)), Node::Metadata(Metadata::Field(j, y))) => {
+                i == j && x != y
+            }
+            (
+                Node::Metadata(Metadata::Parameter(i, x)),
+                Node::Metadata(Metadata::Parameter(j, y)),
+            ) => i == j && x != y,
+            _ => false,
+        }
+    }
 
-        if ast.body.len() > Self::MAX_CODE_SIZE {
-            return Err(CompileError::MaxCodeSize);
+    fn header_name<'input>(f: &File<'input>) -> Option<&'input str> {
+        f.header.iter().find_map(|n| match n {
+            Node::Metadata(Metadata::Name(i)) => Some(*i),
+            _ => None,
+        })
+    }
+
+    /// Returns the elements `f` extends, most basic first.
+    fn bases<'a, 'input>(
+        files: &'a [File<'input>],
+        f: &'a File<'input>,
+    ) -> Result<Vec<&'a File<'input>>, CompileError<'input>> {
+        let mut bases: Vec<&File> = Vec::new();
+        // The names along the chain from `f`, to report a cycle.
+        let mut names = vec![Self::header_name(f).unwrap_or_default()];
+        let mut cur = f;
+        while let Some(name) = cur.header.iter().find_map(|n| match n {
+            Node::Metadata(Metadata::Extends(i)) => Some(*i),
+            _ => None,
+        }) {
+            let base = files
+                .iter()
+                .find(|b| Self::header_name(b) == Some(name))
+                .ok_or(CompileError::UndefinedBase(name))?;
+            let seen = std::iter::once(f)
+                .chain(bases.iter().copied())
+                .position(|b| std::ptr::eq(b, base));
+            if let Some(i) = seen {
+                names.push(name);
+                return Err(CompileError::ExtendsCycle(names[i..].join(" -> ")));
+            }
+            bases.push(base);
+            names.push(name);
+            cur = base;
         }
+        bases.reverse();
+        Ok(bases)
+    }
+
+    /// Replaces each `Node::SiteCount` with a push of the number of sites within `radius`.
+    fn resolve_site_count<'input>(body: &[Node<'input>], radius: u8) -> Vec<Node<'input>> {
+        let count = base::window_size(radius) as u8;
+        body.iter()
+            .map(|n| match n {
+                Node::SiteCount => Node::Instruction(Instruction::Push(count.into())),
+                n => *n,
+            })
+            .collect()
+    }
 
-        let mut label_map: HashMap<&'input str, u16> = HashMap::new();
-        let mut const_map: HashMap<&'input str, Const> = HashMap::new();
+    /// Compiles `ast` with the code of each of `bases` prepended in turn.
+    ///
+    /// The element inherits the fields and parameters of its bases. Each body resolves labels
+    /// against its own first and then against the other bodies, the more derived taking
+    /// precedence, so a derived element may call subroutines defined by its bases.
+    fn compile_element<'input, W: WriteBytesExt>(
+        &mut self,
+        w: &mut W,
+        ast: &File<'input>,
+        bases: &[&File<'input>],
+    ) -> Result<(), CompileError<'input>> {
+        trace!("{:?}", ast);
+
+        let header: Vec<Node<'input>> = bases
+            .iter()
+            .flat_map(|b| b.header.iter())
+            .filter(|n| {
+                matches!(
+                    n,
+                    Node::Metadata(Metadata::Field(_, _))
+                        | Node::Metadata(Metadata::Parameter(_, _))
+                )
+            })
+            .chain(ast.header.iter().filter(|n| {
+                !matches!(
+                    n,
+                    Node::Metadata(Metadata::Extends(_)) | Node::Metadata(Metadata::Include(_))
+                )
+            }))
+            .copied()
+            .collect();
+        let radius = header
+            .iter()
+            .find_map(|n| match n {
+                Node::Metadata(Metadata::Radius(r)) => Some(*r),
+                _ => None,
+            })
+            .unwrap_or(0);
+        let bodies: Vec<Vec<Node<'input>>> = bases
+            .iter()
+            .map(|b| &b.body)
+            .chain(std::iter::once(&ast.body))
+            .map(|b| Self::resolve_site_count(b, radius))
+            .collect();
+
+        let mut const_map: IndexMap<&'input str, Const> = IndexMap::new();
         let mut field_map: HashMap<&'input str, base::FieldSelector> = Self::new_field_map();
 
-        for n in ast.header.iter() {
+        self.self_name.clear();
+        for n in header.iter() {
             Self::index_metadata_node(
                 *n,
                 &mut self.type_map,
@@ -305,16 +808,56 @@ impl Compiler {
                 &mut self.self_name,
             )?;
         }
+        if self.self_name.is_empty() {
+            return Err(CompileError::MissingName);
+        }
 
-        let code_lines = {
-            let mut ln = 0u16;
-            for n in ast.body.iter() {
-                Self::index_code_node(&mut ln, *n, &mut label_map)?;
+        // Counted after lowering so that expanded blocks count against the code size, and labels,
+        // which emit no code, do not.
+        let mut ln = 0u16;
+        let mut body_labels = Vec::new();
+        for body in bodies.iter() {
+            let mut m = HashMap::new();
+            for n in body.iter() {
+                Self::index_code_node(&mut ln, *n, &mut m)?;
             }
-            ln
-        };
+            body_labels.push(m);
+        }
+        let code_lines = ln;
+        let label_maps: Vec<HashMap<&'input str, u16>> = (0..bodies.len())
+            .map(|k| {
+                let mut m = HashMap::new();
+                for labels in body_labels.iter().chain(std::iter::once(&body_labels[k])) {
+                    m.extend(labels.iter());
+                }
+                m
+            })
+            .collect();
 
-        trace!("{:?}", label_map);
+        if self.verify_stack {
+            let (code, targets): (Vec<Instruction<'input>>, Vec<Option<u16>>) = bodies
+                .iter()
+                .zip(label_maps.iter())
+                .flat_map(|(body, label_map)| {
+                    body.iter().filter_map(move |n| match n {
+                        Node::Instruction(i) => {
+                            Some(Self::branch_target(i, label_map).map(|t| (*i, t)))
+                        }
+                        _ => None,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .unzip();
+            ast::check_stack_depth(&code, |ip| targets[ip].map(usize::from), true).map_err(
+                |f| CompileError::StackImbalance {
+                    ip: f.ip as u16,
+                    depth: f.depth,
+                },
+            )?;
+        }
+
+        trace!("{:?}", label_maps);
         trace!("{:?}", const_map);
         trace!("{:?}", field_map);
         trace!("{:?}", self.type_map);
@@ -325,16 +868,459 @@ impl Compiler {
         Self::write_string(w, self.build_tag.as_str())?;
         w.write_u16::<BigEndian>(self.type_map[&self.self_name])?;
 
-        w.write_u8(ast.header.len() as u8)?;
-        for e in ast.header.iter() {
-            Self::write_metadata(w, *e)?;
+        let strip_docs = self.strip_docs;
+        let header: Vec<&Node<'input>> = header
+            .iter()
+            .filter(|n| {
+                !(strip_docs
+                    && matches!(
+                        n,
+                        Node::Metadata(Metadata::Desc(_))
+                            | Node::Metadata(Metadata::Author(_))
+                            | Node::Metadata(Metadata::License(_))
+                    ))
+            })
+            .collect();
+        let header_size =
+            u8::try_from(header.len()).map_err(|_| CompileError::MaxHeaderSize(header.len()))?;
+        w.write_u8(header_size)?;
+        for e in header.iter() {
+            Self::write_metadata(w, **e)?;
         }
+        // The element's own body sees every label, with its own winning over its bases'.
+        Self::write_code_index(w, label_maps.last().unwrap())?;
 
         w.write_u16::<BigEndian>(code_lines)?;
-        for e in ast.body.iter() {
-            Self::write_instruction(w, *e, &self.type_map, &label_map, &const_map, &field_map)?;
+        for (body, label_map) in bodies.iter().zip(label_maps.iter()) {
+            for e in body.iter() {
+                Self::write_instruction(w, *e, &self.type_map, label_map, &const_map, &field_map)?;
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_operands_rejected() {
+        let mut v = Vec::new();
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n  jump -1\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::ParseError(_))
+        ));
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n  gettype -1\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_number_too_large() {
+        let lit = "999999999999999999999999999999999999999999";
+        let src = format!(".name \"A\"\n  push {}\n", lit);
+        let mut v = Vec::new();
+        match Compiler::new("test").compile_to_writer(&mut v, &src, &mut Includes::new()) {
+            Err(CompileError::ParseIntError { start, end, .. }) => {
+                assert_eq!(&src[start..end], lit)
+            }
+            x => panic!("unexpected result: {:?}", x.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_symmetries_directive() {
+        let bits = |src| match substrate::MetadataParser::new().parse(src) {
+            Ok(Node::Metadata(Metadata::Symmetries(s))) => s.bits(),
+            x => panic!("unexpected: {:?}", x),
+        };
+        assert_eq!(bits(".symmetries R000L|R090L"), 0x03);
+        assert_eq!(bits(".symmetries 0x03"), 0x03);
+        assert_eq!(bits(".symmetries 0b11"), 0x03);
+        assert_eq!(bits(".symmetries 3"), 0x03);
+        assert_eq!(bits(".symmetries ALL"), 0xff);
+        assert_eq!(bits(".symmetries 0xff"), 0xff);
+        assert!(substrate::MetadataParser::new()
+            .parse(".symmetries 0x100")
+            .is_err());
+    }
+
+    #[test]
+    fn test_label_hash_collision() {
+        let mut v = Vec::new();
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\nliquid:\ncostarring:\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::LabelHashCollision(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_missing_name() {
+        let mut compiler = Compiler::new("test");
+        let mut v = Vec::new();
+        assert!(matches!(
+            compiler.compile_to_writer(&mut v, "  push1\n  pop\n", &mut Includes::new()),
+            Err(CompileError::MissingName)
+        ));
+    }
+
+    #[test]
+    fn test_undefined_field() {
+        let mut v = Vec::new();
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n  getfield nonexistent\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::UndefinedField("nonexistent"))
+        ));
+    }
+
+    #[test]
+    fn test_undefined_label() {
+        let mut v = Vec::new();
+        for op in &["call", "jump", "jumpzero", "jumpnonzero"] {
+            let src = format!(".name \"A\"\n  {} nowhere\n", op);
+            for &verify_stack in &[false, true] {
+                let mut compiler = Compiler::new("test");
+                compiler.verify_stack = verify_stack;
+                assert!(matches!(
+                    compiler.compile_to_writer(&mut v, &src, &mut Includes::new()),
+                    Err(CompileError::UndefinedLabel("nowhere"))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_undefined_parameter() {
+        let mut v = Vec::new();
+        for op in &["getparameter", "getparamidx"] {
+            let src = format!(".name \"A\"\n  {} nonexistent\n", op);
+            assert!(matches!(
+                Compiler::new("test").compile_to_writer(&mut v, &src, &mut Includes::new()),
+                Err(CompileError::UndefinedParameter("nonexistent"))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_field_out_of_range() {
+        let mut v = Vec::new();
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n.field x,90,8\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::FieldOutOfRange("x", _))
+        ));
+        Compiler::new("test")
+            .compile_to_writer(
+                &mut v,
+                ".name \"A\"\n.field x,63,8\n  getfield x\n",
+                &mut Includes::new(),
+            )
+            .unwrap();
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n.field x,96,0\n",
+                &mut Includes::new()
+            ),
+            Ok(())
+        ));
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n.field x,100,0\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::FieldOutOfRange("x", _))
+        ));
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n.field x,128,0\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::ParseError(lalrpop_util::ParseError::User {
+                error: SyntaxError::OutOfRange(_)
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_verify_stack() {
+        let compile = |src: &'static str| {
+            let mut compiler = Compiler::new("test");
+            compiler.verify_stack = true;
+            let mut v = Vec::new();
+            match compiler.compile_to_writer(&mut v, src, &mut Includes::new()) {
+                Ok(()) => Ok(()),
+                Err(CompileError::StackImbalance { ip, depth }) => Err((ip, depth)),
+                Err(e) => panic!("{}", e),
+            }
+        };
+        // Balanced on both arms of the branch, and through a subroutine.
+        compile(
+            ".name \"A\"\n  push1\n  jumpzero skip\n  push2\n  push3\n  add\n  pop\nskip:\n  call f\n  exit\nf:\n  pop\n  ret\n",
+        )
+        .unwrap();
+        assert_eq!(compile(".name \"A\"\n  push1\n  add\n"), Err((1, 1)));
+        assert_eq!(compile(".name \"A\"\n  push1\n  exit\n"), Err((1, 1)));
+        assert_eq!(compile(".name \"A\"\n  push1\n"), Err((1, 1)));
+        assert_eq!(
+            compile(".name \"A\"\n  push1\n  jumpzero end\n  push1\nend:\n  exit\n"),
+            Err((3, 1))
+        );
+        assert_eq!(
+            compile(".name \"A\"\nloop:\n  push1\n  jump loop\n"),
+            Err((0, 1))
+        );
+        // The jumps of a `foreachsite` loop are followed, including a `break`.
+        compile(".name \"A\"\n.radius 1\n  push0\n  foreachsite {\n    pop\n  }\n  pop\n").unwrap();
+        compile(".name \"A\"\n.radius 1\n  foreachsite {\n    pop\n    break\n  }\n").unwrap();
+        assert!(compile(
+            ".name \"A\"\n.radius 1\n  push0\n  foreachsite {\n    pop\n    pop\n  }\n"
+        )
+        .is_err());
+        assert!(compile(".name \"A\"\n.radius 1\n  foreachsite {\n  }\n").is_err());
+        // Not checked without the pass.
+        let mut v = Vec::new();
+        Compiler::new("test")
+            .compile_to_writer(
+                &mut v,
+                ".name \"A\"\n  push1\n  add\n",
+                &mut Includes::new(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("ewac-include-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, src: &str| std::fs::write(dir.join(name), src).unwrap();
+        write(
+            "shared.ewal",
+            ".field count,0,8\n.parameter step 2\n.include \"base.ewal\"\n",
+        );
+        write("base.ewal", ".field flag,8,1\n");
+        write("loop.ewal", ".include \"again.ewal\"\n");
+        write("again.ewal", ".include \"loop.ewal\"\n");
+        write("clash.ewal", ".field count,0,4\n");
+        // Nested paths are relative to the including file.
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        write("sub/a.ewal", ".include \"b.ewal\"\n");
+        write("sub/b.ewal", ".field deep,0,4\n");
+        let compile = |src: &str| {
+            let mut compiler = Compiler::new("test");
+            compiler.include_dir = Some(dir.clone());
+            let mut v = Vec::new();
+            compiler
+                .compile_to_writer(&mut v, src, &mut Includes::new())
+                .map(|_| v)
+                .map_err(|e| format!("{:?}", e))
+        };
+
+        let a = compile(
+            ".name \"A\"\n.include \"shared.ewal\"\n  getparameter step\n  push0\n  setfield count\n\
+             .name \"B\"\n.include \"shared.ewal\"\n.include \"base.ewal\"\n  push1\n  getsitefield flag\n",
+        );
+        let b = compile(".name \"A\"\n  push0\n  getfield count\n");
+        let cycle = compile(".name \"A\"\n.include \"loop.ewal\"\n");
+        let clash = compile(".name \"A\"\n.include \"shared.ewal\"\n.include \"clash.ewal\"\n");
+        let own = compile(".name \"A\"\n.include \"shared.ewal\"\n.parameter step 3\n");
+        let same = compile(".name \"A\"\n.include \"shared.ewal\"\n.field count,0,8\n");
+        let nested = compile(".name \"A\"\n.include \"sub/a.ewal\"\n  push0\n  getfield deep\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(a.is_ok(), "{:?}", a);
+        assert!(b.unwrap_err().contains("UndefinedField(\"count\")"));
+        assert!(cycle
+            .unwrap_err()
+            .contains("IncludeCycle(\"loop.ewal -> again.ewal -> loop.ewal\")"));
+        assert!(clash
+            .unwrap_err()
+            .contains("DuplicateDefinition(\"count\")"));
+        assert!(own.unwrap_err().contains("DuplicateDefinition(\"step\")"));
+        assert!(same.is_ok(), "{:?}", same);
+        assert!(nested.is_ok(), "{:?}", nested);
+    }
+
+    #[test]
+    fn test_type_names() {
+        let mut v = Vec::new();
+        Compiler::new("test")
+            .compile_to_writer(
+                &mut v,
+                ".name \"A\"\n  gettype \"B\"\n  siteis \"B\"\n.name \"B\"\n",
+                &mut Includes::new(),
+            )
+            .unwrap();
+        for src in &[
+            ".name \"A\"\n  gettype \"C\"\n",
+            ".name \"A\"\n  push1\n  siteis \"C\"\n",
+            ".name \"A\"\n  neighborcount \"C\"\n",
+        ] {
+            assert!(matches!(
+                Compiler::new("test").compile_to_writer(&mut v, src, &mut Includes::new()),
+                Err(CompileError::UnknownType("C"))
+            ));
+        }
+
+        // Elements of other sources resolve once declared.
+        let (a, c) = (".name \"A\"\n  gettype \"C\"\n", ".name \"C\"\n");
+        let mut compiler = Compiler::new("test");
+        compiler.declare_types(a).unwrap();
+        compiler.declare_types(c).unwrap();
+        compiler
+            .compile_to_writer(&mut v, a, &mut Includes::new())
+            .unwrap();
+        assert_eq!(compiler.type_map["C"], 2);
+    }
+
+    #[test]
+    fn test_operand_targets() {
+        let mut v = Vec::new();
+        Compiler::new("test")
+            .compile_to_writer(
+                &mut v,
+                ".name \"A\"\nloop:\n  gettype \"A\"\n  jumpzero loop\n  call loop\n  jump loop\n",
+                &mut Includes::new(),
+            )
+            .unwrap();
+        assert_eq!(
+            &v[v.len() - 12..],
+            &[11, 0, 1, 86, 0, 0, 64, 0, 0, 84, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut a = Vec::new();
+        Compiler::new("test")
+            .compile_to_writer(
+                &mut a,
+                ".name \"A\"\n  push1\n  drop\n",
+                &mut Includes::new(),
+            )
+            .unwrap();
+        let mut b = Vec::new();
+        Compiler::new("test")
+            .compile_to_writer(
+                &mut b,
+                ".name \"A\"\n  push1\n  pop\n",
+                &mut Includes::new(),
+            )
+            .unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a[a.len() - 1], 59);
+    }
+
+    #[test]
+    fn test_for_each_symmetry() {
+        let mut v = Vec::new();
+        Compiler::new("test")
+            .compile_to_writer(
+                &mut v,
+                ".name \"A\"\nforeachsymmetry ALL {\n  push1\n  getsite\n}\n",
+                &mut Includes::new(),
+            )
+            .unwrap();
+        let mut want = vec![0, 40];
+        for i in 0..8 {
+            want.extend_from_slice(&[14, 15, 1 << i, 18, 6, 16]);
+        }
+        assert_eq!(&v[v.len() - want.len()..], want.as_slice());
+    }
+
+    #[test]
+    fn test_extends_errors() {
+        let mut v = Vec::new();
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n.extends \"B\"\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::UndefinedBase("B"))
+        ));
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n.extends \"B\"\n.name \"B\"\n.extends \"A\"\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::ExtendsCycle(c)) if c == "A -> B -> A"
+        ));
+        // The cycle is named from where it closes, not from the element being compiled.
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"C\"\n.extends \"A\"\n.name \"A\"\n.extends \"B\"\n.name \"B\"\n.extends \"A\"\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::ExtendsCycle(c)) if c == "A -> B -> A"
+        ));
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(
+                &mut v,
+                ".name \"A\"\n.extends \"A\"\n",
+                &mut Includes::new()
+            ),
+            Err(CompileError::ExtendsCycle(c)) if c == "A -> A"
+        ));
+    }
+
+    #[test]
+    fn test_max_header_size() {
+        let mut v = Vec::new();
+        let src = format!(".name \"A\"\n{}", ".desc \"x\"\n".repeat(300));
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(&mut v, &src, &mut Includes::new()),
+            Err(CompileError::MaxHeaderSize(301))
+        ));
+    }
+
+    #[test]
+    fn test_max_code_size() {
+        // Each copy of the block adds a save, use and restore around the body.
+        let n = Compiler::MAX_CODE_SIZE / 8 - 3;
+        let src = format!(
+            ".name \"A\"\nforeachsymmetry ALL {{\n{}}}\n",
+            "  nop\n".repeat(n)
+        );
+        let mut v = Vec::new();
+        Compiler::new("test")
+            .compile_to_writer(&mut v, &src, &mut Includes::new())
+            .unwrap();
+
+        let src = format!(
+            ".name \"A\"\nforeachsymmetry ALL {{\n{}}}\n",
+            "  nop\n".repeat(n + 1)
+        );
+        let mut v = Vec::new();
+        assert!(matches!(
+            Compiler::new("test").compile_to_writer(&mut v, &src, &mut Includes::new()),
+            Err(CompileError::MaxCodeSize)
+        ));
+    }
+}
@@ -5,7 +5,7 @@ mod ast;
 mod base;
 mod code;
 
-use crate::code::Compiler;
+use crate::code::{Compiler, Includes};
 use atty::Stream;
 use std::env;
 use std::fs;
@@ -36,6 +36,18 @@ struct Cli {
     )]
     build_tag: String,
 
+    #[structopt(
+        long = "strip-docs",
+        help = "Omit .desc, .author and .license metadata from the output."
+    )]
+    strip_docs: bool,
+
+    #[structopt(
+        long = "verify-stack",
+        help = "Reject elements whose stack can underflow or is left unbalanced at exit."
+    )]
+    verify_stack: bool,
+
     #[structopt(short = "q", long = "quiet", help = "Silence all logging output.")]
     quiet: bool,
 
@@ -86,16 +98,34 @@ fn ewac_main(args: &Cli) {
     };
 
     let mut compiler = Compiler::new(args.build_tag.as_str());
+    compiler.strip_docs = args.strip_docs;
+    compiler.verify_stack = args.verify_stack;
+
+    let sources: Vec<String> = args
+        .input
+        .iter()
+        .map(|i| {
+            let mut file = File::open(Path::new::<String>(i)).expect("Failed to open input file");
+            let mut s = String::new();
+            file.read_to_string(&mut s)
+                .expect("Failed to read input file");
+            s
+        })
+        .collect();
+    // Number every element up front so that inputs may refer to elements of later inputs.
+    for s in sources.iter() {
+        compiler
+            .declare_types(s.as_str())
+            .expect("Failed to compile input file");
+    }
 
-    for i in &args.input {
+    let mut includes = Includes::new();
+    for (i, s) in args.input.iter().zip(sources.iter()) {
         let filename = Path::new::<String>(&i);
-        let mut file = File::open(filename).expect("Failed to open input file");
         let mut v = Vec::new();
-        let mut s = String::new();
-        file.read_to_string(&mut s)
-            .expect("Failed to read input file");
+        compiler.include_dir = filename.parent().map(Path::to_path_buf);
         compiler
-            .compile_to_writer(&mut v, s.as_str())
+            .compile_to_writer(&mut v, s.as_str(), &mut includes)
             .expect("Failed to compile input file");
 
         if is_pipe {
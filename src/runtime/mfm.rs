@@ -3,6 +3,7 @@ use crate::base::arith::Const;
 use crate::base::color;
 use crate::base::color::Color;
 use crate::base::{FieldSelector, Symmetries};
+use crate::runtime::{Cursor, Error, Runtime};
 use colored::*;
 use image::RgbaImage;
 use indexmap::map::Entry;
@@ -10,9 +11,12 @@ use indexmap::IndexMap;
 use lazy_static::lazy_static;
 use log::trace;
 use rand;
-use rand::RngCore;
+use rand::rngs::SmallRng;
+use rand::{RngCore, SeedableRng};
+use rayon::prelude::*;
 use std::cmp::min;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 
 #[derive(Clone, Debug)]
 pub struct Metadata {
@@ -26,7 +30,8 @@ pub struct Metadata {
     pub fg_color: color::Color,
     pub symmetries: Symmetries,
     pub field_map: HashMap<String, base::FieldSelector>,
-    pub parameter_map: HashMap<String, Const>,
+    pub parameter_map: IndexMap<String, Const>,
+    pub wrapping: bool,
     pub type_num: u16,
 }
 
@@ -49,78 +54,403 @@ impl Metadata {
             bg_color: 0.into(),
             symmetries: 0.into(),
             field_map: HashMap::new(),
-            parameter_map: HashMap::new(),
+            parameter_map: IndexMap::new(),
+            wrapping: false,
             type_num: 0,
         }
     }
 
+    /// Looks up the field declared with `name`.
+    pub fn field(&self, name: &str) -> Option<&FieldSelector> {
+        self.field_map.get(name)
+    }
+
+    /// Looks up the parameter declared with `name`.
+    pub fn parameter(&self, name: &str) -> Option<&Const> {
+        self.parameter_map.get(name)
+    }
+
+    /// Returns a new atom of this type.
+    ///
+    /// A parameter with the same name as a field gives that field its initial value, so `.field
+    /// count` and `.parameter count 3` produce atoms whose `count` starts at 3.
     pub fn new_atom(&self) -> Const {
         let mut a = Const::Unsigned(0);
+        for (name, c) in self.parameter_map.iter() {
+            if let Some(f) = self.field(name) {
+                a.store(*c, f);
+            }
+        }
         a.store(self.type_num.into(), &FieldSelector::TYPE);
         a
     }
 }
 
+impl Hash for Metadata {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.symbol.hash(state);
+        self.descs.hash(state);
+        self.authors.hash(state);
+        self.licenses.hash(state);
+        self.radius.hash(state);
+        self.bg_color.hash(state);
+        self.fg_color.hash(state);
+        self.symmetries.hash(state);
+        let mut fields: Vec<_> = self.field_map.iter().collect();
+        fields.sort_by_key(|(k, _)| *k);
+        fields.hash(state);
+        let mut parameters: Vec<_> = self.parameter_map.iter().collect();
+        parameters.sort_by_key(|(k, _)| *k);
+        parameters.hash(state);
+        self.wrapping.hash(state);
+        self.type_num.hash(state);
+    }
+}
+
+/// A view of the sites around an event.
+///
+/// Sites are addressed by window site number, ordered as in the manual: `i` names the fixed offset
+/// `WINDOW_OFFSETS[i]` from the center. Symmetry is not the window's concern; the runtime maps site
+/// numbers through the cursor symmetry with `map_site` before calling any method here, and
+/// implementations must apply exactly the offset lookup and nothing more.
 pub trait EventWindow {
     /// Reset moves the center of the event window to a new arbitrarily selected site.
     fn reset(&mut self);
 
     fn get(&self, i: usize) -> Const;
 
+    /// Reads field `f` of site `i`. Implementations with a packed representation may override
+    /// this to read only the selected bits.
+    fn get_field(&self, i: usize, f: &FieldSelector) -> Const {
+        self.get(i).apply(f)
+    }
+
+    /// Sets site `i`, numbered by its offset from the window center. This is not a grid cell
+    /// index; grids offer `set_absolute` for those.
     fn set(&mut self, i: usize, v: Const);
 
     fn swap(&mut self, i: usize, j: usize);
 
-    fn get_paint(&self) -> color::Color;
+    /// Returns the paint of the center site.
+    fn get_paint(&self) -> color::Color {
+        self.get_paint_at(0)
+    }
+
+    /// Sets the paint of the center site.
+    fn set_paint(&mut self, c: color::Color) {
+        self.set_paint_at(0, c)
+    }
+
+    /// Returns the paint of site `i`, numbered like `get`. Sites off the grid read as 0.
+    fn get_paint_at(&self, i: usize) -> color::Color;
+
+    /// Sets the paint of site `i`, numbered like `set`. Writes off the grid are ignored.
+    fn set_paint_at(&mut self, i: usize, c: color::Color);
+
+    /// Returns the number of non-empty sites in the whole grid.
+    fn population(&self) -> usize;
+
+    /// Returns the grid index of site `i`, or `None` when the site lies outside the grid.
+    fn index(&self, i: usize) -> Option<usize>;
+}
+
+/// A write made through a `LoggingEventWindow`, addressed by grid index.
+#[derive(Copy, Clone, Debug)]
+pub enum Mutation {
+    Set(usize, Const),
+    Swap(usize, usize),
+    SetPaint(usize, color::Color),
+}
+
+/// An event window which records every write made through it, so that two runs can be compared.
+pub struct LoggingEventWindow<T> {
+    inner: T,
+    log: Vec<Mutation>,
+}
+
+impl<T: EventWindow> LoggingEventWindow<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            log: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns the writes recorded since the last call, oldest first.
+    pub fn drain_log(&mut self) -> Vec<Mutation> {
+        std::mem::take(&mut self.log)
+    }
+}
+
+impl<T: EventWindow> EventWindow for LoggingEventWindow<T> {
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    fn get(&self, i: usize) -> Const {
+        self.inner.get(i)
+    }
+
+    fn get_field(&self, i: usize, f: &FieldSelector) -> Const {
+        self.inner.get_field(i, f)
+    }
+
+    fn set(&mut self, i: usize, v: Const) {
+        if let Some(x) = self.inner.index(i) {
+            self.log.push(Mutation::Set(x, v));
+        }
+        self.inner.set(i, v)
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        if let (Some(x), Some(y)) = (self.inner.index(i), self.inner.index(j)) {
+            self.log.push(Mutation::Swap(x, y));
+        }
+        self.inner.swap(i, j)
+    }
+
+    fn get_paint_at(&self, i: usize) -> color::Color {
+        self.inner.get_paint_at(i)
+    }
+
+    fn set_paint_at(&mut self, i: usize, c: color::Color) {
+        if let Some(x) = self.inner.index(i) {
+            self.log.push(Mutation::SetPaint(x, c));
+        }
+        self.inner.set_paint_at(i, c)
+    }
+
+    fn population(&self) -> usize {
+        self.inner.population()
+    }
+
+    fn index(&self, i: usize) -> Option<usize> {
+        self.inner.index(i)
+    }
+}
+
+impl<T: Reseed> Reseed for LoggingEventWindow<T> {
+    fn reseed(&mut self, seed: u64) {
+        self.inner.reseed(seed)
+    }
+}
+
+impl<T: Rand> Rand for LoggingEventWindow<T> {
+    fn rand_u32(&mut self) -> u32 {
+        self.inner.rand_u32()
+    }
+    fn rand(&mut self) -> Const {
+        self.inner.rand()
+    }
+}
+
+/// An event window which holds back the writes made inside a transaction until it is committed.
+/// Outside a transaction writes pass straight through to the inner window. Buffered writes are
+/// kept by site number, so a transaction must end before the window moves.
+pub struct DeferredWindow<'a, T> {
+    inner: &'a mut T,
+    pending: Option<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    sites: IndexMap<usize, Const>,
+    paint: IndexMap<usize, color::Color>,
+}
+
+impl<'a, T: EventWindow> DeferredWindow<'a, T> {
+    pub fn new(inner: &'a mut T) -> Self {
+        Self {
+            inner,
+            pending: None,
+        }
+    }
+
+    /// Starts buffering writes. Transactions do not nest.
+    pub fn begin(&mut self) -> Result<(), Error> {
+        if self.pending.is_some() {
+            return Err(Error::NestedTransaction);
+        }
+        self.pending = Some(Pending::default());
+        Ok(())
+    }
+
+    /// Applies the writes buffered since `begin`, in the order they were first made.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        let p = self.pending.take().ok_or(Error::NoTransaction)?;
+        for (i, v) in p.sites {
+            self.inner.set(i, v);
+        }
+        for (i, c) in p.paint {
+            self.inner.set_paint_at(i, c);
+        }
+        Ok(())
+    }
+
+    /// Discards the writes buffered since `begin`.
+    pub fn abort(&mut self) -> Result<(), Error> {
+        self.pending.take().ok_or(Error::NoTransaction)?;
+        Ok(())
+    }
+}
+
+impl<T: EventWindow> EventWindow for DeferredWindow<'_, T> {
+    fn reset(&mut self) {
+        self.pending = None;
+        self.inner.reset()
+    }
+
+    fn get(&self, i: usize) -> Const {
+        match self.pending.as_ref().and_then(|p| p.sites.get(&i)) {
+            Some(v) => *v,
+            None => self.inner.get(i),
+        }
+    }
+
+    fn get_field(&self, i: usize, f: &FieldSelector) -> Const {
+        match self.pending.as_ref().and_then(|p| p.sites.get(&i)) {
+            Some(v) => v.apply(f),
+            None => self.inner.get_field(i, f),
+        }
+    }
+
+    fn set(&mut self, i: usize, v: Const) {
+        match self.pending.as_mut() {
+            Some(p) => {
+                if self.inner.index(i).is_some() {
+                    p.sites.insert(i, v);
+                }
+            }
+            None => self.inner.set(i, v),
+        }
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        if self.pending.is_none() {
+            return self.inner.swap(i, j);
+        }
+        if self.inner.index(i).is_some() && self.inner.index(j).is_some() {
+            let (a, b) = (self.get(i), self.get(j));
+            self.set(i, b);
+            self.set(j, a);
+        }
+    }
 
-    fn set_paint(&mut self, c: color::Color);
+    fn get_paint_at(&self, i: usize) -> color::Color {
+        match self.pending.as_ref().and_then(|p| p.paint.get(&i)) {
+            Some(c) => *c,
+            None => self.inner.get_paint_at(i),
+        }
+    }
+
+    fn set_paint_at(&mut self, i: usize, c: color::Color) {
+        match self.pending.as_mut() {
+            Some(p) => {
+                p.paint.insert(i, c);
+            }
+            None => self.inner.set_paint_at(i, c),
+        }
+    }
+
+    /// Returns the population of the inner window, which does not count buffered writes.
+    fn population(&self) -> usize {
+        self.inner.population()
+    }
+
+    fn index(&self, i: usize) -> Option<usize> {
+        self.inner.index(i)
+    }
+}
+
+impl<T: Reseed> Reseed for DeferredWindow<'_, T> {
+    fn reseed(&mut self, seed: u64) {
+        self.inner.reseed(seed)
+    }
+}
+
+impl<T: Rand> Rand for DeferredWindow<'_, T> {
+    fn rand_u32(&mut self) -> u32 {
+        self.inner.rand_u32()
+    }
+    fn rand(&mut self) -> Const {
+        self.inner.rand()
+    }
 }
 
 pub struct MinimalEventWindow<'a, R: RngCore> {
     data: [Const; 41],
     paint: [color::Color; 41],
+    /// Which sites exist. The others lie off the grid the window was copied from.
+    sites: [bool; 41],
     rng: &'a mut R,
 }
 
 impl<'a, R: RngCore> MinimalEventWindow<'a, R> {
     pub fn new(rng: &'a mut R) -> Self {
+        Self::with_sites(rng, [true; 41])
+    }
+
+    /// Like `new`, but the sites where `sites` is false are off the grid, as at the edge of a
+    /// `DenseGrid` without wrapping: they read as Empty, have no index and ignore writes.
+    pub fn with_sites(rng: &'a mut R, sites: [bool; 41]) -> Self {
         Self {
             data: [0.into(); 41],
             paint: [0.into(); 41],
+            sites,
             rng: rng,
         }
     }
+
+    /// Returns `i` if it is a site in the window.
+    fn site(&self, i: usize) -> Option<usize> {
+        match self.sites.get(i) {
+            Some(true) => Some(i),
+            _ => None,
+        }
+    }
 }
 
 impl<R: RngCore> EventWindow for MinimalEventWindow<'_, R> {
     fn reset(&mut self) {}
 
     fn get(&self, i: usize) -> Const {
-        self.data.get(i).map(|x| *x).unwrap_or(0.into())
+        self.site(i).map(|i| self.data[i]).unwrap_or(0.into())
     }
 
     fn set(&mut self, i: usize, v: Const) {
-        if let Some(site) = self.data.get_mut(i) {
-            *site = v;
+        if let Some(i) = self.site(i) {
+            self.data[i] = v;
         }
     }
 
     fn swap(&mut self, i: usize, j: usize) {
-        let n = self.data.len();
-        if i != j && i < n && j < n {
+        if let (Some(i), Some(j)) = (self.site(i), self.site(j)) {
             self.data.swap(i, j)
         }
     }
 
-    fn get_paint(&self) -> color::Color {
-        *self.paint.get(0).unwrap_or(&0.into())
+    fn get_paint_at(&self, i: usize) -> color::Color {
+        self.site(i).map(|i| self.paint[i]).unwrap_or(0.into())
     }
 
-    fn set_paint(&mut self, c: color::Color) {
-        if let Some(color) = self.paint.get_mut(0) {
-            *color = c;
+    fn set_paint_at(&mut self, i: usize, c: color::Color) {
+        if let Some(i) = self.site(i) {
+            self.paint[i] = c;
         }
     }
+
+    fn population(&self) -> usize {
+        self.data.iter().filter(|x| !x.is_zero()).count()
+    }
+
+    fn index(&self, i: usize) -> Option<usize> {
+        self.site(i)
+    }
 }
 
 pub trait Rand {
@@ -128,6 +458,18 @@ pub trait Rand {
     fn rand(&mut self) -> Const;
 }
 
+/// Restarts the random stream of a window, so that a driver can repeat trials exactly.
+pub trait Reseed {
+    /// Replaces the generator with one seeded from `seed`.
+    fn reseed(&mut self, seed: u64);
+}
+
+impl<'a, R: RngCore + SeedableRng> Reseed for MinimalEventWindow<'a, R> {
+    fn reseed(&mut self, seed: u64) {
+        *self.rng = R::seed_from_u64(seed);
+    }
+}
+
 impl<'a, R: RngCore> Rand for MinimalEventWindow<'a, R> {
     fn rand_u32(&mut self) -> u32 {
         self.rng.next_u32()
@@ -154,8 +496,8 @@ lazy_static! {
         /* 10 = */ (0, -2),
         /* 11 = */ (0, 2),
         /* 12 = */ (2, 0),
-        /* 13 = */ (2, -1),
-        /* 14 = */ (2, 1),
+        /* 13 = */ (-2, -1),
+        /* 14 = */ (-2, 1),
         /* 15 = */ (-1, -2),
         /* 16 = */ (-1, 2),
         /* 17 = */ (1, -2),
@@ -185,6 +527,22 @@ lazy_static! {
     ];
 }
 
+/// Returns the `(x, y)` offset of each site from the center, indexed by site number. External
+/// renderers can use this to lay out windows the same way the engine does; `site_at` is the
+/// inverse.
+pub fn window_offsets() -> &'static [(isize, isize); 41] {
+    &WINDOW_OFFSETS
+}
+
+/// Returns the site at offset `(x, y)` from the center, if it lies within the event window.
+pub fn site_at(x: isize, y: isize) -> Option<u8> {
+    if x.abs() + y.abs() > 4 {
+        None
+    } else {
+        Some(offset_to_site(&(x, y)))
+    }
+}
+
 fn offset_to_site(offset: &(isize, isize)) -> u8 {
     match offset {
         (0, 0) => 0,
@@ -200,8 +558,8 @@ fn offset_to_site(offset: &(isize, isize)) -> u8 {
         (0, -2) => 10,
         (0, 2) => 11,
         (2, 0) => 12,
-        (2, -1) => 13,
-        (2, 1) => 14,
+        (-2, -1) => 13,
+        (-2, 1) => 14,
         (-1, -2) => 15,
         (-1, 2) => 16,
         (1, -2) => 17,
@@ -232,18 +590,21 @@ fn offset_to_site(offset: &(isize, isize)) -> u8 {
     }
 }
 
+/// Maps site `x` through the symmetry `s`.
+///
+/// NONE is treated as the identity (R000L). A set of several symmetries is reduced to its lowest
+/// member, the same choice `select_symmetries` makes for `r = 0`.
 pub fn map_site(x: u8, s: Symmetries) -> u8 {
     if let Some(wo) = WINDOW_OFFSETS.get(x as usize) {
-        let offset = match s {
-            Symmetries::R000L => *wo,
+        let offset = match select_symmetries(0, s) {
             Symmetries::R090L => (wo.1, -wo.0),
-            Symmetries::R180L => (-wo.0, wo.1),
-            Symmetries::R270L => (wo.1, wo.0),
-            Symmetries::R000R => (-wo.0, wo.1),
-            Symmetries::R090R => (-wo.1, -wo.0),
-            Symmetries::R180R => (wo.0, wo.1),
-            Symmetries::R270R => (-wo.1, wo.0),
-            i => panic!("map_site: bad symmetries: {:?}", i),
+            Symmetries::R180L => (-wo.0, -wo.1),
+            Symmetries::R270L => (-wo.1, wo.0),
+            Symmetries::R000R => (wo.0, -wo.1),
+            Symmetries::R090R => (wo.1, wo.0),
+            Symmetries::R180R => (-wo.0, wo.1),
+            Symmetries::R270R => (-wo.1, -wo.0),
+            _ => *wo,
         };
         offset_to_site(&offset)
     } else {
@@ -305,22 +666,7 @@ pub fn debug_event_window<T: EventWindow>(
                 s.push(VOID);
             }
             for _ in 0..2 * $cols + 1 {
-                let x = ew.get(PRINT_INDICES[idx]);
-                let typ: u16 = x.apply(&FieldSelector::TYPE).into();
-                let meta = type_map.get(&typ);
-                if let Some(meta) = meta {
-                    let (r, g, b, _) = meta.fg_color.components();
-                    let (b_r, b_g, b_b, _) = meta.bg_color.components();
-                    s.push_str(
-                        format!(
-                            "{}",
-                            meta.symbol.truecolor(r, g, b).on_truecolor(b_r, b_g, b_b)
-                        )
-                        .as_str(),
-                    );
-                } else {
-                    s.push(UNKNOWN);
-                }
+                push_symbol(&mut s, ew.get(PRINT_INDICES[idx]), type_map);
                 idx += 1;
             }
             for _ in 0..4 - $cols {
@@ -342,6 +688,41 @@ pub fn debug_event_window<T: EventWindow>(
     w.write_all(s.as_bytes())
 }
 
+/// Appends the symbol of the element of atom `x` in its colors, or `UNKNOWN`.
+fn push_symbol(s: &mut String, x: Const, type_map: &HashMap<u16, Metadata>) {
+    let typ: u16 = x.apply(&FieldSelector::TYPE).into();
+    if let Some(meta) = type_map.get(&typ) {
+        let (r, g, b, _) = meta.fg_color.components();
+        let (b_r, b_g, b_b, _) = meta.bg_color.components();
+        s.push_str(
+            format!(
+                "{}",
+                meta.symbol.truecolor(r, g, b).on_truecolor(b_r, b_g, b_b)
+            )
+            .as_str(),
+        );
+    } else {
+        s.push(UNKNOWN);
+    }
+}
+
+/// Writes every cell of a `size` grid, one row per line, like `debug_event_window`.
+fn render_cells<W: std::io::Write>(
+    w: &mut W,
+    size: Bounds,
+    get_cell: impl Fn(usize, usize) -> Const,
+    type_map: &HashMap<u16, Metadata>,
+) -> std::io::Result<()> {
+    let mut s = String::new();
+    for y in 0..size.height {
+        for x in 0..size.width {
+            push_symbol(&mut s, get_cell(x, y), type_map);
+        }
+        s.push('\n');
+    }
+    w.write_all(s.as_bytes())
+}
+
 #[derive(Copy, Clone, Debug)]
 struct Bounds {
     pub width: usize,
@@ -363,16 +744,42 @@ pub struct DenseGrid<'a, R: RngCore> {
     size: Bounds,
     scale: usize,
     origin: usize,
+    inset: usize,
+    wrap: bool,
+    /// The pool `run_parallel` last ran on and its thread count, kept for the next call.
+    pool: Option<(usize, rayon::ThreadPool)>,
     rng: &'a mut R,
 }
 
 impl<'a, R: RngCore> DenseGrid<'a, R> {
-    pub fn new(rng: &'a mut R, size: (usize, usize)) -> Self {
+    /// The default limit on the number of cells in a grid.
+    pub const MAX_CELLS: usize = 1 << 24;
+
+    pub fn new(rng: &'a mut R, size: (usize, usize)) -> Result<Self, Error> {
         Self::with_scale(rng, 1, size)
     }
 
-    pub fn with_scale(rng: &'a mut R, scale: usize, size: (usize, usize)) -> Self {
-        Self {
+    /// Returns an error if either dimension of `size` is zero or the grid would have more than
+    /// `MAX_CELLS` cells.
+    pub fn with_scale(rng: &'a mut R, scale: usize, size: (usize, usize)) -> Result<Self, Error> {
+        Self::with_max_cells(rng, scale, size, Self::MAX_CELLS)
+    }
+
+    /// Like `with_scale`, but with a limit of `max_cells` cells in place of `MAX_CELLS`.
+    pub fn with_max_cells(
+        rng: &'a mut R,
+        scale: usize,
+        size: (usize, usize),
+        max_cells: usize,
+    ) -> Result<Self, Error> {
+        if size.0 == 0 || size.1 == 0 {
+            return Err(Error::BadGridSize(size.0, size.1));
+        }
+        match size.0.checked_mul(size.1) {
+            Some(n) if n <= max_cells => {}
+            _ => return Err(Error::GridTooLarge(size.0, size.1)),
+        }
+        Ok(Self {
             data: {
                 let mut v = Vec::with_capacity(size.0 * size.1);
                 (0..size.0 * size.1).for_each(|_| v.push(0.into()));
@@ -386,67 +793,301 @@ impl<'a, R: RngCore> DenseGrid<'a, R> {
             size: size.into(),
             scale: scale,
             origin: rng.next_u64() as usize % (size.0 * size.1),
+            inset: 0,
+            wrap: false,
+            pool: None,
             rng: rng,
+        })
+    }
+
+    /// Like `new`, but `reset` only picks origins at least `radius` cells from every edge, so a
+    /// window of that radius never falls off the grid. Returns an error if the grid is too small
+    /// to fit such a window.
+    pub fn with_inset(rng: &'a mut R, size: (usize, usize), radius: usize) -> Result<Self, Error> {
+        let mut grid = Self::new(rng, size)?;
+        if size.0 <= 2 * radius || size.1 <= 2 * radius {
+            return Err(Error::BadGridSize(size.0, size.1));
         }
+        grid.inset = radius;
+        grid.reset();
+        Ok(grid)
     }
-}
 
-impl<R: RngCore> EventWindow for DenseGrid<'_, R> {
-    fn reset(&mut self) {
-        self.origin = self.rng.next_u64() as usize % self.data.len()
+    /// Configures toroidal edges. When set, window offsets wrap around both axes of the grid.
+    /// Otherwise sites past an edge read as Empty and ignore writes.
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
     }
 
-    fn get(&self, i: usize) -> Const {
-        if let Some(wi) = WINDOW_OFFSETS.get(i) {
-            let i = (self.origin as isize) + wi.1 * self.size.width as isize + wi.0;
-            if i >= 0 {
-                return *self.data.get(i as usize).unwrap_or(&0.into());
+    /// Returns the cell index of window site `i`, if it lies on the grid.
+    fn cell(&self, i: usize) -> Option<usize> {
+        Self::cell_at(self.size, self.wrap, self.origin, i)
+    }
+
+    /// Returns the cell index of site `i` of a window centered on cell `origin`.
+    fn cell_at(size: Bounds, wrap: bool, origin: usize, i: usize) -> Option<usize> {
+        let wi = WINDOW_OFFSETS.get(i)?;
+        let (width, height) = (size.width as isize, size.height as isize);
+        let mut x = (origin % size.width) as isize + wi.0;
+        let mut y = (origin / size.width) as isize + wi.1;
+        if wrap {
+            x = x.rem_euclid(width);
+            y = y.rem_euclid(height);
+        } else if x < 0 || x >= width || y < 0 || y >= height {
+            return None;
+        }
+        Some((y * width + x) as usize)
+    }
+
+    /// Returns the number of cells in the grid.
+    pub fn cells(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the atom at cell `(x, y)`, or Empty outside the grid.
+    pub fn get_cell(&self, x: usize, y: usize) -> Const {
+        if x < self.size.width && y < self.size.height {
+            self.data[y * self.size.width + x]
+        } else {
+            0.into()
+        }
+    }
+
+    /// Sets the atom at cell `(x, y)`. Returns false if the cell is outside the grid.
+    pub fn set_cell(&mut self, x: usize, y: usize, v: Const) -> bool {
+        if x < self.size.width && y < self.size.height {
+            self.set_absolute(y * self.size.width + x, v)
+        } else {
+            false
+        }
+    }
+
+    /// Sets window site `i`, numbered by its offset from the current window center. This is the
+    /// same as `EventWindow::set`.
+    pub fn set_window(&mut self, i: usize, v: Const) {
+        EventWindow::set(self, i, v);
+    }
+
+    /// Sets the cell at row-major index `index`, the index space used by `try_place` and
+    /// `blit_image`. Returns false if the index is outside the grid.
+    pub fn set_absolute(&mut self, index: usize, v: Const) -> bool {
+        match self.data.get_mut(index) {
+            Some(site) => {
+                *site = v;
+                true
             }
+            None => false,
         }
-        0.into()
     }
 
-    fn set(&mut self, i: usize, v: Const) {
-        if let Some(wi) = WINDOW_OFFSETS.get(i) {
-            let i = (self.origin as isize) + wi.1 * self.size.width as isize + wi.0;
-            if i >= 0 {
-                if let Some(site) = self.data.get_mut(i as usize) {
-                    *site = v;
-                }
+    /// Places `atom` at the absolute cell `index` only if the cell is empty.
+    ///
+    /// Returns whether the atom was placed.
+    pub fn try_place(&mut self, index: usize, atom: Const) -> bool {
+        match self.data.get_mut(index) {
+            Some(site) if site.is_zero() => {
+                *site = atom;
+                true
             }
+            _ => false,
         }
     }
 
-    fn swap(&mut self, i: usize, j: usize) {
-        let wi = WINDOW_OFFSETS.get(i);
-        if wi == None {
-            return;
+    /// Writes the whole grid row by row, each cell as its element's symbol in the element's
+    /// colors, or `?` for types missing from `type_map`.
+    pub fn render_grid<W: std::io::Write>(
+        &self,
+        w: &mut W,
+        type_map: &HashMap<u16, Metadata>,
+    ) -> std::io::Result<()> {
+        render_cells(w, self.size, |x, y| self.get_cell(x, y), type_map)
+    }
+
+    /// Splits the region where windows may be centered into blocks at least `PARALLEL_BLOCK`
+    /// cells on a side and groups them by checkerboard color. Returns each group as a list of
+    /// `(x, y, width, height)` blocks. With wrapping on, an odd block count along an axis would
+    /// make the last and first blocks neighbors of the same color, so the last gets a third color.
+    fn parallel_blocks(&self) -> Vec<Vec<(usize, usize, usize, usize)>> {
+        let width = self.size.width - 2 * self.inset;
+        let height = self.size.height - 2 * self.inset;
+        let bounds = |len: usize| {
+            let n = (len / Self::PARALLEL_BLOCK).max(1);
+            let colors = if self.wrap && n > 1 && n % 2 == 1 {
+                3
+            } else {
+                2
+            };
+            let color = move |k: usize| if colors == 3 && k == n - 1 { 2 } else { k % 2 };
+            (
+                (0..n).map(move |k| (k * len / n, (k + 1) * len / n, color(k))),
+                colors,
+            )
+        };
+        let (xs, x_colors) = bounds(width);
+        let (ys, y_colors) = bounds(height);
+        let mut groups = vec![Vec::new(); x_colors * y_colors];
+        for (y0, y1, cy) in ys {
+            for (x0, x1, cx) in xs.clone() {
+                groups[cy * x_colors + cx].push((
+                    x0 + self.inset,
+                    y0 + self.inset,
+                    x1 - x0,
+                    y1 - y0,
+                ));
+            }
         }
-        let wj = WINDOW_OFFSETS.get(j);
-        if wj == None {
-            return;
+        groups.retain(|g| !g.is_empty());
+        groups
+    }
+
+    /// Side of the blocks `run_parallel` works in. Windows centered in two blocks of the same
+    /// color are at least this far apart along some axis, and radius 4 windows 9 or more apart
+    /// never share a site.
+    const PARALLEL_BLOCK: usize = 9;
+
+    /// Runs `events` events of `runtime` on up to `threads` threads, or rayon's default with 0.
+    ///
+    /// The grid is split into a checkerboard of blocks. Each phase runs one event centered in
+    /// every block of one color at once, and no two of those windows share a site. Each event
+    /// draws from its own generator, seeded from one draw of the grid generator and the event
+    /// number, so a run is reproducible whatever the thread count, though it differs from a run
+    /// of the same seed through `Runtime::run`. Wrapping grids must be at least 9 cells on a side
+    /// so no window reaches itself. When fewer events remain than a phase has blocks, the blocks
+    /// which run are a rotation of the group from a random start, so no block is favored.
+    pub fn run_parallel(
+        &mut self,
+        runtime: &Runtime<'_>,
+        events: u64,
+        threads: usize,
+    ) -> Result<(), Error> {
+        if self.wrap
+            && (self.size.width < Self::PARALLEL_BLOCK || self.size.height < Self::PARALLEL_BLOCK)
+        {
+            return Err(Error::BadGridSize(self.size.width, self.size.height));
         }
-        let (w1, w2) = (wi.unwrap(), wj.unwrap());
-        let i1 = (self.origin as isize) + w1.1 * self.size.width as isize + w1.0;
-        if i1 < 0 {
-            return;
+        if !matches!(self.pool, Some((t, _)) if t == threads) {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?;
+            self.pool = Some((threads, pool));
         }
-        let i2 = (self.origin as isize) + w2.1 * self.size.width as isize + w2.0;
-        let n = self.data.len() as isize;
-        if i1 != i2 && i2 >= 0 && i1 < n && i2 < n {
-            self.data.swap(i1 as usize, i2 as usize);
+        let groups = self.parallel_blocks();
+        let seed = self.rng.next_u64();
+        let (size, wrap) = (self.size, self.wrap);
+        let mut n = 0;
+        while n < events {
+            for group in &groups {
+                let count = group.len().min((events - n) as usize);
+                let start = if count < group.len() {
+                    self.rng.next_u64() as usize % group.len()
+                } else {
+                    0
+                };
+                let blocks: Vec<_> = (0..count)
+                    .map(|k| group[(start + k) % group.len()])
+                    .collect();
+                let (data, paint) = (&self.data, &self.paint);
+                let pool = &self.pool.as_ref().unwrap().1;
+                let results: Vec<_> = pool.install(|| {
+                    blocks
+                        .par_iter()
+                        .enumerate()
+                        .map(|(k, &(x, y, w, h))| {
+                            let mut rng = SmallRng::seed_from_u64(seed.wrapping_add(n + k as u64));
+                            let i = rng.next_u64() as usize % (w * h);
+                            let origin = (y + i / w) * size.width + x + i % w;
+                            let cells: Vec<_> = (0..WINDOW_OFFSETS.len())
+                                .map(|i| Self::cell_at(size, wrap, origin, i))
+                                .collect();
+                            let mut sites = [false; 41];
+                            for (s, c) in sites.iter_mut().zip(&cells) {
+                                *s = c.is_some();
+                            }
+                            let mut ew = MinimalEventWindow::with_sites(&mut rng, sites);
+                            for (i, c) in cells.iter().enumerate() {
+                                if let Some(c) = *c {
+                                    ew.data[i] = data[c];
+                                    ew.paint[i] = paint[c];
+                                }
+                            }
+                            let mut cursor = Cursor::new();
+                            runtime.reset_cursor(&mut ew, &mut cursor);
+                            Runtime::execute(
+                                &mut ew,
+                                &mut cursor,
+                                &runtime.code_map,
+                                &runtime.type_map,
+                            )?;
+                            Ok::<_, Error>((cells, ew.data, ew.paint))
+                        })
+                        .collect()
+                });
+                for res in results {
+                    let (cells, atoms, colors) = res?;
+                    for (i, c) in cells.into_iter().enumerate() {
+                        if let Some(c) = c {
+                            self.data[c] = atoms[i];
+                            self.paint[c] = colors[i];
+                        }
+                    }
+                }
+                n += count as u64;
+                if n >= events {
+                    break;
+                }
+            }
         }
+        Ok(())
     }
+}
 
-    fn get_paint(&self) -> color::Color {
-        *self.paint.get(self.origin).unwrap_or(&0.into())
+impl<R: RngCore> EventWindow for DenseGrid<'_, R> {
+    fn reset(&mut self) {
+        let width = self.size.width - 2 * self.inset;
+        let height = self.size.height - 2 * self.inset;
+        let i = self.rng.next_u64() as usize % (width * height);
+        self.origin = (i / width + self.inset) * self.size.width + i % width + self.inset;
     }
 
-    fn set_paint(&mut self, c: color::Color) {
-        if let Some(color) = self.paint.get_mut(self.origin) {
-            *color = c;
+    fn get(&self, i: usize) -> Const {
+        self.cell(i).map(|i| self.data[i]).unwrap_or(0.into())
+    }
+
+    fn set(&mut self, i: usize, v: Const) {
+        if let Some(i) = self.cell(i) {
+            self.data[i] = v;
         }
     }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        if let (Some(i), Some(j)) = (self.cell(i), self.cell(j)) {
+            self.data.swap(i, j);
+        }
+    }
+
+    fn get_paint_at(&self, i: usize) -> color::Color {
+        self.cell(i).map(|i| self.paint[i]).unwrap_or(0.into())
+    }
+
+    fn set_paint_at(&mut self, i: usize, c: color::Color) {
+        if let Some(i) = self.cell(i) {
+            self.paint[i] = c;
+        }
+    }
+
+    fn population(&self) -> usize {
+        self.data.iter().filter(|x| !x.is_zero()).count()
+    }
+
+    fn index(&self, i: usize) -> Option<usize> {
+        self.cell(i)
+    }
+}
+
+impl<'a, R: RngCore + SeedableRng> Reseed for DenseGrid<'a, R> {
+    fn reseed(&mut self, seed: u64) {
+        *self.rng = R::seed_from_u64(seed);
+    }
 }
 
 impl<'a, R: RngCore> Rand for DenseGrid<'a, R> {
@@ -471,12 +1112,9 @@ impl<R: RngCore> Blit for DenseGrid<'_, R> {
         let (width, height) = im.dimensions();
         for x in 0..min(self.size.width, width as usize) {
             for y in 0..min(self.size.height, height as usize) {
-                let pix = im.get_pixel(x as u32, y as u32);
-                let mut c = (pix.0[0] as u32) << 24;
-                c |= (pix.0[1] as u32) << 16;
-                c |= (pix.0[2] as u32) << 8;
-                c |= pix.0[3] as u32;
-                self.paint[y * self.size.width + x] = c.into();
+                let [r, g, b, a] = im.get_pixel(x as u32, y as u32).0;
+                let c = Color::from_components(r, g, b, a);
+                self.paint[y * self.size.width + x] = c;
             }
         }
     }
@@ -502,20 +1140,91 @@ pub struct SparseGrid<'a, R: RngCore> {
 }
 
 impl<'a, R: RngCore> SparseGrid<'a, R> {
-    pub fn new(rng: &'a mut R, size: (usize, usize)) -> Self {
+    pub fn new(rng: &'a mut R, size: (usize, usize)) -> Result<Self, Error> {
         Self::with_scale(rng, 1, size)
     }
 
-    pub fn with_scale(rng: &'a mut R, scale: usize, size: (usize, usize)) -> Self {
-        Self {
+    /// Returns an error if either dimension of `size` is zero.
+    pub fn with_scale(rng: &'a mut R, scale: usize, size: (usize, usize)) -> Result<Self, Error> {
+        if size.0 == 0 || size.1 == 0 {
+            return Err(Error::BadGridSize(size.0, size.1));
+        }
+        Ok(Self {
             data: IndexMap::new(),
             paint: IndexMap::new(),
             size: size.into(),
             scale: scale,
             origin: rng.next_u64() as usize % (size.0 * size.1),
             rng: rng,
+        })
+    }
+
+    /// Returns the number of cells in the grid.
+    pub fn cells(&self) -> usize {
+        self.size.width * self.size.height
+    }
+
+    /// Returns the atom at cell `(x, y)`, or Empty outside the grid.
+    pub fn get_cell(&self, x: usize, y: usize) -> Const {
+        if x < self.size.width && y < self.size.height {
+            *self
+                .data
+                .get(&(y * self.size.width + x))
+                .unwrap_or(&0.into())
+        } else {
+            0.into()
         }
     }
+
+    /// Writes the whole grid like `DenseGrid::render_grid`.
+    pub fn render_grid<W: std::io::Write>(
+        &self,
+        w: &mut W,
+        type_map: &HashMap<u16, Metadata>,
+    ) -> std::io::Result<()> {
+        render_cells(w, self.size, |x, y| self.get_cell(x, y), type_map)
+    }
+
+    /// Sets the atom at cell `(x, y)`. Returns false if the cell is outside the grid.
+    pub fn set_cell(&mut self, x: usize, y: usize, v: Const) -> bool {
+        if x >= self.size.width || y >= self.size.height {
+            return false;
+        }
+        self.set_absolute(y * self.size.width + x, v)
+    }
+
+    /// Sets window site `i`, numbered by its offset from the current window center. This is the
+    /// same as `EventWindow::set`.
+    pub fn set_window(&mut self, i: usize, v: Const) {
+        EventWindow::set(self, i, v);
+    }
+
+    /// Sets the cell at row-major index `index`, the index space used by `try_place`. Returns
+    /// false if the index is outside the grid.
+    pub fn set_absolute(&mut self, index: usize, v: Const) -> bool {
+        if index >= self.cells() {
+            return false;
+        }
+        if v.is_zero() {
+            self.data.remove(&index);
+        } else {
+            self.data.insert(index, v);
+        }
+        true
+    }
+
+    /// Places `atom` at the absolute cell `index` only if the cell is empty.
+    ///
+    /// Returns whether the atom was placed.
+    pub fn try_place(&mut self, index: usize, atom: Const) -> bool {
+        if index >= self.cells() || self.data.contains_key(&index) {
+            return false;
+        }
+        if !atom.is_zero() {
+            self.data.insert(index, atom);
+        }
+        true
+    }
 }
 
 impl<R: RngCore> EventWindow for SparseGrid<'_, R> {
@@ -529,27 +1238,21 @@ impl<R: RngCore> EventWindow for SparseGrid<'_, R> {
     }
 
     fn get(&self, i: usize) -> Const {
-        if let Some(wi) = WINDOW_OFFSETS.get(i) {
-            let i = (self.origin as isize) + wi.1 * self.size.width as isize + wi.0;
-            if i >= 0 {
-                return *self.data.get(&(i as usize)).unwrap_or(&0.into());
-            }
+        if let Some(i) = self.index(i) {
+            return *self.data.get(&i).unwrap_or(&0.into());
         }
         0.into()
     }
 
     fn set(&mut self, i: usize, v: Const) {
-        if let Some(wi) = WINDOW_OFFSETS.get(i) {
-            let i = (self.origin as isize) + wi.1 * self.size.width as isize + wi.0;
-            if i >= 0 {
-                if v.is_zero() {
-                    self.data.remove(&(i as usize));
-                } else {
-                    match self.data.entry(i as usize) {
-                        Entry::Occupied(o) => *o.into_mut() = v,
-                        Entry::Vacant(e) => {
-                            e.insert(v);
-                        }
+        if let Some(i) = self.index(i) {
+            if v.is_zero() {
+                self.data.remove(&i);
+            } else {
+                match self.data.entry(i) {
+                    Entry::Occupied(o) => *o.into_mut() = v,
+                    Entry::Vacant(e) => {
+                        e.insert(v);
                     }
                 }
             }
@@ -562,15 +1265,22 @@ impl<R: RngCore> EventWindow for SparseGrid<'_, R> {
         self.set(j, t);
     }
 
-    fn get_paint(&self) -> color::Color {
-        self.paint.get(&self.origin).map(|x| *x).unwrap_or(0.into())
+    fn get_paint_at(&self, i: usize) -> color::Color {
+        self.index(i)
+            .and_then(|i| self.paint.get(&i))
+            .map(|x| *x)
+            .unwrap_or(0.into())
     }
 
-    fn set_paint(&mut self, c: color::Color) {
+    fn set_paint_at(&mut self, i: usize, c: color::Color) {
+        let i = match self.index(i) {
+            Some(i) => i,
+            None => return,
+        };
         if c.bits() == 0 {
-            self.paint.remove(&self.origin);
+            self.paint.remove(&i);
         } else {
-            match self.paint.entry(self.origin) {
+            match self.paint.entry(i) {
                 Entry::Occupied(o) => *o.into_mut() = c,
                 Entry::Vacant(v) => {
                     v.insert(c);
@@ -578,6 +1288,21 @@ impl<R: RngCore> EventWindow for SparseGrid<'_, R> {
             }
         }
     }
+
+    fn population(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Sites past any edge of the grid are off the grid, as in a `DenseGrid` without wrapping.
+    fn index(&self, i: usize) -> Option<usize> {
+        DenseGrid::<R>::cell_at(self.size, false, self.origin, i)
+    }
+}
+
+impl<'a, R: RngCore + SeedableRng> Reseed for SparseGrid<'a, R> {
+    fn reseed(&mut self, seed: u64) {
+        *self.rng = R::seed_from_u64(seed);
+    }
 }
 
 impl<'a, R: RngCore> Rand for SparseGrid<'a, R> {
@@ -596,14 +1321,11 @@ impl<R: RngCore> Blit for SparseGrid<'_, R> {
         let (width, height) = im.dimensions();
         for x in 0..min(self.size.width, width as usize) {
             for y in 0..min(self.size.height, height as usize) {
-                let pix = im.get_pixel(x as u32, y as u32);
-                let mut c = (pix.0[0] as u32) << 24;
-                c |= (pix.0[1] as u32) << 16;
-                c |= (pix.0[2] as u32) << 8;
-                c |= pix.0[3] as u32;
+                let [r, g, b, a] = im.get_pixel(x as u32, y as u32).0;
+                let c = Color::from_components(r, g, b, a);
                 match self.paint.entry(y * self.size.width + x) {
-                    Entry::Occupied(o) => *o.into_mut() = c.into(),
-                    Entry::Vacant(v) => *v.insert(0.into()) = c.into(),
+                    Entry::Occupied(o) => *o.into_mut() = c,
+                    Entry::Vacant(v) => *v.insert(0.into()) = c,
                 }
             }
         }
@@ -622,9 +1344,201 @@ impl<R: RngCore> Blit for SparseGrid<'_, R> {
     }
 }
 
+/// A toroidal grid divided into equal rectangular tiles.
+///
+/// `reset` visits the tiles in turn, row by row, and picks the new window center uniformly within
+/// the current tile. Windows still read and write across tile boundaries and wrap at the world
+/// edges. With a single tile this is a wrapping `DenseGrid`.
+pub struct TiledGrid<'a, R: RngCore> {
+    grid: DenseGrid<'a, R>,
+    tile: Bounds,
+    tiles: Bounds,
+    current: usize,
+}
+
+impl<'a, R: RngCore> TiledGrid<'a, R> {
+    /// Returns an error if `tile` has a zero dimension or does not evenly divide `size`, or on any
+    /// size `DenseGrid::new` rejects.
+    pub fn new(rng: &'a mut R, size: (usize, usize), tile: (usize, usize)) -> Result<Self, Error> {
+        if tile.0 == 0
+            || tile.1 == 0
+            || !size.0.is_multiple_of(tile.0)
+            || !size.1.is_multiple_of(tile.1)
+        {
+            return Err(Error::BadGridSize(tile.0, tile.1));
+        }
+        let mut grid = DenseGrid::new(rng, size)?;
+        grid.set_wrap(true);
+        Ok(Self {
+            grid,
+            tile: tile.into(),
+            tiles: (size.0 / tile.0, size.1 / tile.1).into(),
+            current: 0,
+        })
+    }
+
+    /// Returns the number of tiles.
+    pub fn tiles(&self) -> usize {
+        self.tiles.width * self.tiles.height
+    }
+
+    /// Returns the tile holding the current window center, numbered row by row.
+    pub fn tile(&self) -> usize {
+        let x = self.grid.origin % self.grid.size.width / self.tile.width;
+        let y = self.grid.origin / self.grid.size.width / self.tile.height;
+        y * self.tiles.width + x
+    }
+
+    /// Returns the number of cells in the grid.
+    pub fn cells(&self) -> usize {
+        self.grid.cells()
+    }
+
+    /// Returns the atom at cell `(x, y)`, or Empty outside the grid.
+    pub fn get_cell(&self, x: usize, y: usize) -> Const {
+        self.grid.get_cell(x, y)
+    }
+
+    /// Writes the whole grid like `DenseGrid::render_grid`.
+    pub fn render_grid<W: std::io::Write>(
+        &self,
+        w: &mut W,
+        type_map: &HashMap<u16, Metadata>,
+    ) -> std::io::Result<()> {
+        self.grid.render_grid(w, type_map)
+    }
+
+    /// Sets the atom at cell `(x, y)`. Returns false if the cell is outside the grid.
+    pub fn set_cell(&mut self, x: usize, y: usize, v: Const) -> bool {
+        self.grid.set_cell(x, y, v)
+    }
+
+    /// Sets window site `i`, numbered by its offset from the current window center.
+    pub fn set_window(&mut self, i: usize, v: Const) {
+        self.grid.set_window(i, v)
+    }
+
+    /// Sets the cell at row-major index `index`. Returns false if the index is outside the grid.
+    pub fn set_absolute(&mut self, index: usize, v: Const) -> bool {
+        self.grid.set_absolute(index, v)
+    }
+
+    /// Places `atom` at the absolute cell `index` only if the cell is empty.
+    pub fn try_place(&mut self, index: usize, atom: Const) -> bool {
+        self.grid.try_place(index, atom)
+    }
+}
+
+impl<R: RngCore> EventWindow for TiledGrid<'_, R> {
+    fn reset(&mut self) {
+        let (tx, ty) = (
+            self.current % self.tiles.width,
+            self.current / self.tiles.width,
+        );
+        self.current = (self.current + 1) % self.tiles();
+        let i = self.grid.rng.next_u64() as usize % (self.tile.width * self.tile.height);
+        let x = tx * self.tile.width + i % self.tile.width;
+        let y = ty * self.tile.height + i / self.tile.width;
+        self.grid.origin = y * self.grid.size.width + x;
+    }
+
+    fn get(&self, i: usize) -> Const {
+        self.grid.get(i)
+    }
+
+    fn get_field(&self, i: usize, f: &FieldSelector) -> Const {
+        self.grid.get_field(i, f)
+    }
+
+    fn set(&mut self, i: usize, v: Const) {
+        self.grid.set(i, v)
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.grid.swap(i, j)
+    }
+
+    fn get_paint_at(&self, i: usize) -> color::Color {
+        self.grid.get_paint_at(i)
+    }
+
+    fn set_paint_at(&mut self, i: usize, c: color::Color) {
+        self.grid.set_paint_at(i, c)
+    }
+
+    fn population(&self) -> usize {
+        self.grid.population()
+    }
+
+    fn index(&self, i: usize) -> Option<usize> {
+        self.grid.index(i)
+    }
+}
+
+impl<'a, R: RngCore + SeedableRng> Reseed for TiledGrid<'a, R> {
+    fn reseed(&mut self, seed: u64) {
+        self.grid.reseed(seed)
+    }
+}
+
+impl<'a, R: RngCore> Rand for TiledGrid<'a, R> {
+    fn rand_u32(&mut self) -> u32 {
+        self.grid.rand_u32()
+    }
+    fn rand(&mut self) -> Const {
+        self.grid.rand()
+    }
+}
+
+impl<R: RngCore> Blit for TiledGrid<'_, R> {
+    fn blit_image(&mut self, im: &RgbaImage) {
+        self.grid.blit_image(im)
+    }
+
+    fn unblit_image(&self, im: &mut RgbaImage) {
+        self.grid.unblit_image(im)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::base::DataType;
+    use rand::rngs::mock::StepRng;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_new_atom_field_defaults() {
+        let mut elem = Metadata::new();
+        elem.type_num = 7;
+        let count = FieldSelector {
+            offset: 0,
+            length: 4,
+            data_type: DataType::Unsigned,
+        };
+        elem.field_map.insert("count".to_string(), count);
+        elem.parameter_map
+            .insert("count".to_string(), Const::Unsigned(3));
+        elem.parameter_map
+            .insert("unrelated".to_string(), Const::Unsigned(5));
+        let a = elem.new_atom();
+        assert_eq!(u128::from(a.apply(&count)), 3);
+        assert_eq!(u16::from(a.apply(&FieldSelector::TYPE)), 7);
+        assert_eq!(a.count_ones(), 2 + 3);
+    }
+
+    #[test]
+    fn test_metadata_lookup() {
+        let mut elem = Metadata::new();
+        elem.field_map
+            .insert("count".to_string(), FieldSelector::TYPE);
+        elem.parameter_map
+            .insert("limit".to_string(), Const::Unsigned(3));
+        assert_eq!(elem.field("count"), Some(&FieldSelector::TYPE));
+        assert_eq!(elem.field("limit"), None);
+        assert!(matches!(elem.parameter("limit"), Some(Const::Unsigned(3))));
+        assert!(elem.parameter("count").is_none());
+    }
 
     #[test]
     fn test_sample_none_symmetries() {
@@ -681,4 +1595,604 @@ mod tests {
         assert_eq!(sample_symmetries(&mut rng, 255.into()), Symmetries::R180R);
         assert_eq!(sample_symmetries(&mut rng, 255.into()), Symmetries::R270R);
     }
+
+    #[test]
+    fn test_window_offsets_round_trip() {
+        for i in 0..41 {
+            assert_eq!(offset_to_site(&WINDOW_OFFSETS[i]), i as u8);
+        }
+    }
+
+    #[test]
+    fn test_window_offsets() {
+        for (i, &(x, y)) in window_offsets().iter().enumerate() {
+            assert_eq!(site_at(x, y), Some(map_site(i as u8, Symmetries::R000L)));
+        }
+        assert_eq!(site_at(4, 1), None);
+    }
+
+    #[test]
+    fn test_map_site_symmetries() {
+        let all: Vec<Symmetries> = (0..8).map(|i| (1u8 << i).into()).collect();
+        let west: Vec<u8> = all.iter().map(|s| map_site(1, *s)).collect();
+        assert_eq!(west, vec![1, 3, 4, 2, 1, 2, 4, 3]);
+        // Site 13 has no symmetry of its own so every transform sends it somewhere distinct.
+        let mut images: Vec<u8> = all.iter().map(|s| map_site(13, *s)).collect();
+        images.sort();
+        images.dedup();
+        assert_eq!(images.len(), 8);
+    }
+
+    #[test]
+    fn test_map_site_none_symmetries() {
+        for i in 0..41 {
+            assert_eq!(map_site(i, 0.into()), map_site(i, Symmetries::R000L));
+        }
+    }
+
+    #[test]
+    fn test_map_site_many_symmetries() {
+        for i in 0..41 {
+            assert_eq!(
+                map_site(i, Symmetries::R090L | Symmetries::R180R),
+                map_site(i, Symmetries::R090L)
+            );
+        }
+        for i in 0..41 {
+            assert_eq!(map_site(i, 255.into()), map_site(i, Symmetries::R000L));
+        }
+    }
+
+    #[test]
+    fn test_try_place() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut dense = DenseGrid::new(&mut rng, (2, 2)).unwrap();
+        for i in 0..4 {
+            assert!(dense.try_place(i, (i as u32 + 1).into()));
+        }
+        for i in 0..5 {
+            assert!(!dense.try_place(i, 9u32.into()));
+        }
+        assert_eq!(dense.data, (1..5u32).map(Const::from).collect::<Vec<_>>());
+
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut sparse = SparseGrid::new(&mut rng, (2, 2)).unwrap();
+        for i in 0..4 {
+            assert!(sparse.try_place(i, (i as u32 + 1).into()));
+        }
+        for i in 0..5 {
+            assert!(!sparse.try_place(i, 9u32.into()));
+        }
+        for i in 0..4 {
+            assert_eq!(sparse.data[&i], Const::from(i as u32 + 1));
+        }
+    }
+
+    #[test]
+    fn test_dense_grid_wrap() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut ew = DenseGrid::new(&mut rng, (4, 3)).unwrap();
+        let atom = Const::from(1u8);
+        ew.set_cell(0, 0, atom);
+        // Center the window on the far corner; (0, 0) is one step east and one step south.
+        ew.origin = 2 * 4 + 3;
+        assert!(ew.get(4).is_zero());
+        assert!(ew.get(3).is_zero());
+        ew.set_wrap(true);
+        ew.origin = 3;
+        assert_eq!(ew.get(4), atom);
+        ew.origin = 2 * 4;
+        assert_eq!(ew.get(3), atom);
+        ew.swap(0, 3);
+        assert_eq!(ew.get(0), atom);
+        assert_eq!(ew.get_cell(0, 2), atom);
+        assert!(ew.get_cell(0, 0).is_zero());
+    }
+
+    #[test]
+    fn test_get_field() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut ew = MinimalEventWindow::new(&mut rng);
+        ew.set(0, Const::Unsigned(0xdead_beef << 80 | 0x1234));
+        ew.set(3, Const::Signed(-1));
+        let fields = [
+            FieldSelector::TYPE,
+            FieldSelector::DATA,
+            FieldSelector {
+                offset: 4,
+                length: 8,
+                data_type: DataType::Unsigned,
+            },
+        ];
+        for i in [0, 3, 5].iter() {
+            for f in fields.iter() {
+                assert_eq!(ew.get_field(*i, f), ew.get(*i).apply(f));
+            }
+        }
+    }
+
+    #[test]
+    fn test_logging_event_window() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut grid = DenseGrid::new(&mut rng, (4, 3)).unwrap();
+        grid.origin = 4 + 1;
+        let mut ew = LoggingEventWindow::new(grid);
+        let atom = Const::from(1u8);
+        ew.set(1, atom);
+        ew.swap(0, 2);
+        ew.set_paint(0xff.into());
+        assert_eq!(ew.get(1), atom);
+        let log = ew.drain_log();
+        assert!(matches!(
+            log[..],
+            [
+                Mutation::Set(4, _),
+                Mutation::Swap(5, 1),
+                Mutation::SetPaint(5, _)
+            ]
+        ));
+        assert!(ew.drain_log().is_empty());
+
+        // Writes which fall outside the grid are dropped and not logged.
+        let mut grid = ew.into_inner();
+        grid.origin = 0;
+        let mut ew = LoggingEventWindow::new(grid);
+        ew.set(1, atom);
+        assert!(ew.drain_log().is_empty());
+    }
+
+    #[test]
+    fn test_deferred_window() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut grid = DenseGrid::new(&mut rng, (4, 3)).unwrap();
+        grid.origin = 4 + 1;
+        let atom = Const::from(1u8);
+        grid.set(1, atom);
+        let mut ew = DeferredWindow::new(&mut grid);
+        assert!(matches!(ew.commit(), Err(Error::NoTransaction)));
+        ew.begin().unwrap();
+        assert!(matches!(ew.begin(), Err(Error::NestedTransaction)));
+        ew.swap(1, 4);
+        ew.set_paint(0xff.into());
+        // Reads inside the transaction see the buffered writes.
+        assert_eq!(ew.get(4), atom);
+        assert_eq!(ew.get(1), Const::from(0u8));
+        assert_eq!(ew.get_paint().bits(), 0xff);
+        ew.abort().unwrap();
+        assert_eq!(ew.get(1), atom);
+        assert_eq!(ew.get_paint().bits(), 0);
+
+        ew.begin().unwrap();
+        ew.swap(1, 4);
+        ew.commit().unwrap();
+        assert_eq!(grid.get(4), atom);
+        assert_eq!(grid.get(1), Const::from(0u8));
+    }
+
+    #[test]
+    fn test_dense_grid_inset() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 7);
+        let mut grid = DenseGrid::with_inset(&mut rng, (11, 10), 4).unwrap();
+        let mut origins = std::collections::HashSet::new();
+        for _ in 0..100 {
+            grid.reset();
+            origins.insert(grid.origin);
+            assert!((0..WINDOW_OFFSETS.len()).all(|i| grid.index(i).is_some()));
+        }
+        assert_eq!(origins.len(), 3 * 2);
+
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        assert!(matches!(
+            DenseGrid::with_inset(&mut rng, (9, 8), 4),
+            Err(Error::BadGridSize(9, 8))
+        ));
+        let grid = DenseGrid::with_inset(&mut rng, (9, 9), 4).unwrap();
+        assert_eq!(grid.origin, 4 * 9 + 4);
+    }
+
+    #[test]
+    fn test_window_and_absolute_indices() {
+        let (a, b) = (Const::from(1u8), Const::from(2u8));
+        let (dx, dy) = WINDOW_OFFSETS[1];
+
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut grid = DenseGrid::with_inset(&mut rng, (5, 5), 1).unwrap();
+        let (x, y) = (grid.origin % 5, grid.origin / 5);
+        grid.set_window(1, a);
+        assert!(grid.set_absolute(1, b));
+        assert!(!grid.set_absolute(25, b));
+        assert_eq!(
+            grid.get_cell((x as isize + dx) as usize, (y as isize + dy) as usize),
+            a
+        );
+        assert_eq!(grid.get_cell(1, 0), b);
+        assert_eq!(grid.get(1), a);
+
+        let mut rng = rand::rngs::mock::StepRng::new(12, 0);
+        let mut grid = SparseGrid::new(&mut rng, (5, 5)).unwrap();
+        grid.set_window(1, a);
+        assert!(grid.set_absolute(1, b));
+        assert!(!grid.set_absolute(25, b));
+        assert_eq!(grid.get_cell((2 + dx) as usize, (2 + dy) as usize), a);
+        assert_eq!(grid.get_cell(1, 0), b);
+        assert_eq!(grid.get(1), a);
+    }
+
+    #[test]
+    fn test_blit_paint() {
+        let mut im = RgbaImage::new(3, 3);
+        *im.get_pixel_mut(1, 1) = [0x11, 0x22, 0x33, 0x44].into();
+        let want = "#11223344".parse::<Color>().unwrap().bits();
+
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut grid = DenseGrid::with_inset(&mut rng, (3, 3), 1).unwrap();
+        grid.blit_image(&im);
+        assert_eq!(grid.get_paint().bits(), want);
+        let mut out = RgbaImage::new(3, 3);
+        grid.unblit_image(&mut out);
+        assert_eq!(out, im);
+
+        let mut rng = rand::rngs::mock::StepRng::new(4, 0);
+        let mut grid = SparseGrid::new(&mut rng, (3, 3)).unwrap();
+        grid.blit_image(&im);
+        assert_eq!(grid.get_paint().bits(), want);
+        let mut out = RgbaImage::new(3, 3);
+        grid.unblit_image(&mut out);
+        assert_eq!(out, im);
+    }
+
+    #[test]
+    fn test_paint_at() {
+        fn paint<T: EventWindow>(ew: &mut T) {
+            ew.set_paint_at(3, 0x11223344.into());
+            assert_eq!(ew.get_paint_at(3).bits(), 0x11223344);
+            assert_eq!(ew.get_paint().bits(), 0);
+            ew.set_paint(0xff.into());
+            assert_eq!(ew.get_paint_at(0).bits(), 0xff);
+            assert_eq!(ew.get_paint_at(41).bits(), 0);
+        }
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        paint(&mut MinimalEventWindow::new(&mut rng));
+        paint(&mut DenseGrid::with_inset(&mut rng, (9, 9), 4).unwrap());
+        let mut rng = rand::rngs::mock::StepRng::new(40, 0);
+        paint(&mut SparseGrid::new(&mut rng, (9, 9)).unwrap());
+
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut inner = MinimalEventWindow::new(&mut rng);
+        let mut ew = DeferredWindow::new(&mut inner);
+        ew.begin().unwrap();
+        paint(&mut ew);
+        ew.commit().unwrap();
+        assert_eq!(inner.get_paint_at(3).bits(), 0x11223344);
+    }
+
+    #[test]
+    fn test_reseed() {
+        fn draw<T: Rand + Reseed>(ew: &mut T) -> Vec<u32> {
+            ew.reseed(42);
+            (0..8).map(|_| ew.rand_u32()).collect()
+        }
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(1);
+        let mut ew = MinimalEventWindow::new(&mut rng);
+        let want = draw(&mut ew);
+        assert_eq!(draw(&mut ew), want);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(2);
+        let mut grid = DenseGrid::new(&mut rng, (2, 2)).unwrap();
+        assert_eq!(draw(&mut grid), want);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(3);
+        let mut grid = SparseGrid::new(&mut rng, (2, 2)).unwrap();
+        assert_eq!(draw(&mut grid), want);
+    }
+
+    #[test]
+    fn test_parallel_blocks_disjoint() {
+        for &(size, wrap, inset) in &[
+            ((40, 30), false, 0),
+            ((45, 27), true, 0),
+            ((36, 20), true, 0),
+            ((30, 30), false, 4),
+            ((8, 5), false, 0),
+        ] {
+            let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+            let mut grid = DenseGrid::new(&mut rng, size).unwrap();
+            grid.set_wrap(wrap);
+            grid.inset = inset;
+            let groups = grid.parallel_blocks();
+            let mut centers = 0;
+            for group in &groups {
+                let reach: Vec<HashSet<usize>> = group
+                    .iter()
+                    .map(|&(x, y, w, h)| {
+                        centers += w * h;
+                        let mut s = HashSet::new();
+                        for cy in y..y + h {
+                            for cx in x..x + w {
+                                let origin = cy * size.0 + cx;
+                                s.extend((0..41).filter_map(|i| {
+                                    DenseGrid::<StepRng>::cell_at(grid.size, wrap, origin, i)
+                                }));
+                            }
+                        }
+                        s
+                    })
+                    .collect();
+                for (a, ra) in reach.iter().enumerate() {
+                    for rb in &reach[a + 1..] {
+                        assert!(ra.is_disjoint(rb), "{:?} wrap {}", size, wrap);
+                    }
+                }
+            }
+            assert_eq!(centers, (size.0 - 2 * inset) * (size.1 - 2 * inset));
+        }
+    }
+
+    #[test]
+    fn test_run_parallel() {
+        use crate::ast::Instruction;
+
+        // Each event adds one to the atom at the center, so the total count is order independent.
+        let mut runtime = Runtime::new();
+        let mut elem = Metadata::new();
+        elem.type_num = 1;
+        runtime.type_map.insert(1, elem.clone());
+        runtime.code_map.insert(
+            1,
+            vec![
+                Instruction::Push0,
+                Instruction::Push0,
+                Instruction::GetSite,
+                Instruction::Push1,
+                Instruction::Add,
+                Instruction::SetSite,
+            ],
+        );
+        let checksum = |grid: &DenseGrid<SmallRng>| -> u128 {
+            grid.data
+                .iter()
+                .map(|a| match a.apply(&FieldSelector::DATA) {
+                    Const::Unsigned(x) => x,
+                    x => panic!("unexpected: {:?}", x),
+                })
+                .sum()
+        };
+        let events = 5000;
+        let run = |parallel: Option<usize>| {
+            let mut rng = SmallRng::seed_from_u64(3);
+            let mut grid = DenseGrid::new(&mut rng, (45, 27)).unwrap();
+            grid.set_wrap(true);
+            for i in 0..grid.cells() {
+                grid.set_absolute(i, elem.new_atom());
+            }
+            match parallel {
+                Some(threads) => grid.run_parallel(&runtime, events, threads).unwrap(),
+                None => {
+                    let mut cursor = Cursor::new();
+                    grid.reset();
+                    runtime.reset_cursor(&mut grid, &mut cursor);
+                    let stats = runtime.run(&mut grid, &mut cursor, events, 0, 0, |_, _| {});
+                    assert!(stats.error.is_none());
+                }
+            }
+            (checksum(&grid), grid.data.clone())
+        };
+        let (serial, _) = run(None);
+        let (one, one_data) = run(Some(1));
+        let (four, four_data) = run(Some(4));
+        assert_eq!(serial, events as u128);
+        assert_eq!(one, serial);
+        assert_eq!(four, serial);
+        assert!(one_data == four_data);
+    }
+
+    #[test]
+    fn test_run_parallel_edges() {
+        use crate::ast::Instruction;
+
+        // Swaps with a random neighbor. Without wrapping, swaps off the grid do nothing.
+        let mut runtime = Runtime::new();
+        let mut elem = Metadata::new();
+        elem.type_num = 1;
+        elem.radius = 1;
+        runtime.type_map.insert(1, elem.clone());
+        runtime.code_map.insert(
+            1,
+            vec![
+                Instruction::Push0,
+                Instruction::Push4,
+                Instruction::RandRange,
+                Instruction::Push1,
+                Instruction::Add,
+                Instruction::SwapSites,
+            ],
+        );
+        let mut rng = SmallRng::seed_from_u64(5);
+        let mut grid = DenseGrid::new(&mut rng, (20, 20)).unwrap();
+        for i in (0..grid.cells()).step_by(3) {
+            grid.set_absolute(i, elem.new_atom());
+        }
+        let population = grid.population();
+        for threads in &[1, 4, 4] {
+            grid.run_parallel(&runtime, 5000, *threads).unwrap();
+            assert_eq!(grid.population(), population);
+        }
+    }
+
+    #[test]
+    fn test_render_grid() {
+        colored::control::set_override(true);
+        let mut type_map = HashMap::new();
+        let mut empty = Metadata::new();
+        empty.symbol = ".".to_owned();
+        type_map.insert(0, empty.clone());
+        let mut a = Metadata::new();
+        a.type_num = 1;
+        a.symbol = "A".to_owned();
+        a.fg_color = 0xff0000ffu32.into();
+        type_map.insert(1, a.clone());
+        let mut b = Metadata::new();
+        b.type_num = 2;
+        b.symbol = "B".to_owned();
+        b.bg_color = 0x0000ffffu32.into();
+        type_map.insert(2, b.clone());
+
+        let mut rng = StepRng::new(0, 1);
+        let mut dense = DenseGrid::new(&mut rng, (3, 3)).unwrap();
+        dense.set_cell(0, 0, a.new_atom());
+        dense.set_cell(2, 1, b.new_atom());
+        dense.set_cell(1, 2, Const::from(9u128 << 80));
+        let mut v = Vec::new();
+        dense.render_grid(&mut v, &type_map).unwrap();
+
+        let cell = |m: &Metadata| {
+            let (r, g, bl, _) = m.fg_color.components();
+            let (b_r, b_g, b_b, _) = m.bg_color.components();
+            m.symbol
+                .truecolor(r, g, bl)
+                .on_truecolor(b_r, b_g, b_b)
+                .to_string()
+        };
+        let (e, a, b) = (cell(&empty), cell(&a), cell(&b));
+        let want = [&a, &e, &e, "\n", &e, &e, &b, "\n", &e, "?", &e, "\n"].concat();
+        assert_eq!(String::from_utf8(v).unwrap(), want);
+
+        let mut rng = StepRng::new(0, 1);
+        let mut sparse = SparseGrid::new(&mut rng, (3, 3)).unwrap();
+        for y in 0..3 {
+            for x in 0..3 {
+                sparse.set_cell(x, y, dense.get_cell(x, y));
+            }
+        }
+        let mut v = Vec::new();
+        sparse.render_grid(&mut v, &type_map).unwrap();
+        assert_eq!(String::from_utf8(v).unwrap(), want);
+    }
+
+    #[test]
+    fn test_tiled_grid_single_tile() {
+        let mut dense_rng = rand::rngs::SmallRng::seed_from_u64(5);
+        let mut tiled_rng = rand::rngs::SmallRng::seed_from_u64(5);
+        let mut dense = DenseGrid::new(&mut dense_rng, (7, 5)).unwrap();
+        dense.set_wrap(true);
+        let mut tiled = TiledGrid::new(&mut tiled_rng, (7, 5), (7, 5)).unwrap();
+        assert_eq!(tiled.tiles(), 1);
+        for k in 0..100u32 {
+            dense.reset();
+            tiled.reset();
+            assert_eq!(tiled.index(0), dense.index(0));
+            let (a, b) = (dense.rand(), tiled.rand());
+            assert_eq!(a, b);
+            dense.set(k as usize % 41, k.into());
+            tiled.set(k as usize % 41, k.into());
+            dense.swap(0, 12);
+            tiled.swap(0, 12);
+            dense.set_paint_at(3, k.into());
+            tiled.set_paint_at(3, k.into());
+        }
+        for i in 0..dense.cells() {
+            assert_eq!(tiled.grid.data[i], dense.data[i]);
+            assert_eq!(tiled.grid.paint[i].bits(), dense.paint[i].bits());
+        }
+    }
+
+    #[test]
+    fn test_tiled_grid_tiles() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(5);
+        assert!(matches!(
+            TiledGrid::new(&mut rng, (6, 4), (4, 2)),
+            Err(Error::BadGridSize(4, 2))
+        ));
+        let mut ew = TiledGrid::new(&mut rng, (6, 4), (3, 2)).unwrap();
+        assert_eq!(ew.tiles(), 4);
+        for k in 0..20 {
+            ew.reset();
+            assert_eq!(ew.tile(), k % 4);
+        }
+        // Windows reach across tiles and wrap at the world edges.
+        ew.set_cell(5, 3, Const::from(1u8));
+        ew.grid.origin = 0;
+        assert_eq!(ew.get(5), Const::from(1u8));
+    }
+
+    #[test]
+    fn test_sparse_grid_edges() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut dense = DenseGrid::new(&mut rng, (3, 3)).unwrap();
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut sparse = SparseGrid::new(&mut rng, (3, 3)).unwrap();
+        for origin in 0..9 {
+            dense.origin = origin;
+            sparse.origin = origin;
+            for i in 0..WINDOW_OFFSETS.len() {
+                assert_eq!(
+                    sparse.index(i),
+                    dense.index(i),
+                    "origin {} site {}",
+                    origin,
+                    i
+                );
+            }
+        }
+
+        // Writes past the right and bottom edges do not reach the next row or past the end.
+        for x in 0..9 {
+            sparse.set_absolute(x, 1.into());
+        }
+        sparse.origin = 5; // (2, 1)
+        sparse.set(4, 2.into());
+        assert_eq!(sparse.get(4), 0.into());
+        assert_eq!(sparse.get_cell(0, 2), 1.into());
+        sparse.origin = 0; // (0, 0)
+        assert_eq!(sparse.get(1), 0.into());
+        sparse.origin = 7; // (1, 2)
+        sparse.set(3, 2.into());
+        assert_eq!(sparse.get(3), 0.into());
+        assert_eq!(sparse.population(), 9);
+        assert_eq!(sparse.get(0), 1.into());
+    }
+
+    #[test]
+    fn test_dense_grid_zero_size() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        assert!(matches!(
+            DenseGrid::new(&mut rng, (0, 4)),
+            Err(Error::BadGridSize(0, 4))
+        ));
+        assert!(matches!(
+            DenseGrid::new(&mut rng, (4, 0)),
+            Err(Error::BadGridSize(4, 0))
+        ));
+        assert!(DenseGrid::new(&mut rng, (1, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_sparse_grid_zero_size() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        assert!(matches!(
+            SparseGrid::new(&mut rng, (0, 4)),
+            Err(Error::BadGridSize(0, 4))
+        ));
+        assert!(matches!(
+            SparseGrid::with_scale(&mut rng, 2, (4, 0)),
+            Err(Error::BadGridSize(4, 0))
+        ));
+        assert!(SparseGrid::new(&mut rng, (1, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_dense_grid_too_large() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        assert!(matches!(
+            DenseGrid::new(&mut rng, (100000, 100000)),
+            Err(Error::GridTooLarge(100000, 100000))
+        ));
+        assert!(matches!(
+            DenseGrid::new(&mut rng, (usize::MAX, 2)),
+            Err(Error::GridTooLarge(_, 2))
+        ));
+        assert!(matches!(
+            DenseGrid::with_max_cells(&mut rng, 1, (4, 4), 15),
+            Err(Error::GridTooLarge(4, 4))
+        ));
+        assert!(DenseGrid::with_max_cells(&mut rng, 1, (4, 4), 16).is_ok());
+    }
 }
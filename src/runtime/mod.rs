@@ -1,16 +1,24 @@
 pub mod mfm;
 
+use crate::ast;
 use crate::ast::{Arg, Instruction};
+use crate::base;
 use crate::base::arith::Const;
 use crate::base::{FieldSelector, Symmetries};
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt;
 use log::trace;
-use mfm::{EventWindow, Metadata};
+use mfm::{EventWindow, Metadata, Rand};
+use rand::rngs::SmallRng;
 use rand::RngCore;
+use rand::SeedableRng;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
 use std::io;
 use thiserror;
+use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -36,8 +44,50 @@ pub enum Error {
   NoElement,
   #[error("running unknown element: {0}")]
   UnknownElement(u16),
-  #[error("stack underflow")]
-  StackUnderflow, // TODO: add context
+  #[error("stack underflow at instruction {ip}: {op}")]
+  StackUnderflow { ip: usize, op: &'static str },
+  #[error("bad grid size: {0}x{1}")]
+  BadGridSize(usize, usize),
+  #[error("grid too large: {0}x{1}")]
+  GridTooLarge(usize, usize),
+  #[error("{0} bytes left over after the cursor state")]
+  TrailingCursorData(usize),
+  #[error("op stack overflow")]
+  StackOverflow,
+  #[error("call depth exceeded")]
+  CallDepthExceeded,
+  #[error("stack underflows at instruction: {0}")]
+  StackDiscipline(usize),
+  #[error("parameter index out of range: {0}")]
+  BadParameterIndex(usize),
+  #[error("value out of range: {0}")]
+  OutOfRange(Const),
+  #[error("bad site: {0}")]
+  BadSite(Const),
+  #[error("site outside the element radius: {0}")]
+  SiteOutOfRadius(usize),
+  #[error("begin inside a transaction")]
+  NestedTransaction,
+  #[error("commit or abort outside a transaction")]
+  NoTransaction,
+  #[error("code ends after {got} of {want} instructions")]
+  TruncatedCode { want: u16, got: u16 },
+  #[error("trailing data after the last element")]
+  TrailingData,
+  #[error("division by zero at instruction: {0}")]
+  DivideByZero(usize),
+  #[error("no progress at instruction: {0}")]
+  NoProgress(usize),
+  #[error("no type numbers left to renumber into")]
+  TypeNumbersExhausted,
+  #[error("element has no name")]
+  MissingName,
+  #[error("radius {0} is larger than the event window")]
+  BadRadius(u8),
+  #[error("symbol is not a single display character: {0:?}")]
+  BadSymbol(String),
+  #[error("thread pool error: {0}")]
+  ThreadPool(#[from] rayon::ThreadPoolBuildError),
 }
 
 pub trait RuntimeImpl {
@@ -55,13 +105,26 @@ const MAGIC_NUMBER: u32 = 0x02030741;
 #[derive(Debug)]
 pub struct Cursor {
   ip: usize,
+  /// The mnemonic of the instruction at `ip` while it runs, reported by `StackUnderflow`.
+  op: &'static str,
   symmetry: Symmetries,
   symmetries_stack: Vec<Symmetries>,
   call_stack: Vec<usize>,
   op_stack: Vec<Const>,
+  max_stack_depth: usize,
+  max_call_depth: usize,
+  radius: u8,
+  exited: bool,
+  loop_limit: Option<usize>,
+  /// For each instruction reached with loop detection on, a hash of the state at the last visit
+  /// and the number of visits in a row which found that same state.
+  visits: HashMap<usize, (u64, usize)>,
 }
 
 impl Cursor {
+  pub const MAX_STACK_DEPTH: usize = 1024;
+  pub const MAX_CALL_DEPTH: usize = 256;
+
   pub fn new() -> Self {
     Self::with_symmetry(Symmetries::R000L)
   }
@@ -69,28 +132,312 @@ impl Cursor {
   pub fn with_symmetry(s: Symmetries) -> Self {
     Self {
       ip: 0,
+      op: "",
       symmetry: s,
       symmetries_stack: Vec::new(),
       call_stack: Vec::new(),
       op_stack: Vec::new(),
+      max_stack_depth: Self::MAX_STACK_DEPTH,
+      max_call_depth: Self::MAX_CALL_DEPTH,
+      radius: 4,
+      exited: false,
+      loop_limit: None,
+      visits: HashMap::new(),
     }
   }
 
+  /// Sets the deepest the op stack and call stack may grow before execution fails with
+  /// `StackOverflow` or `CallDepthExceeded`.
+  pub fn set_limits(&mut self, max_stack_depth: usize, max_call_depth: usize) {
+    self.max_stack_depth = max_stack_depth;
+    self.max_call_depth = max_call_depth;
+  }
+
   pub fn reset(&mut self, s: Symmetries) {
     self.ip = 0;
     self.symmetry = s;
     self.symmetries_stack.clear();
     self.call_stack.clear();
     self.op_stack.clear();
+    self.exited = false;
+    self.visits.clear();
+  }
+
+  /// Turns on loop detection, which fails execution with `NoProgress` once an instruction is
+  /// reached more than `limit` times in a row with nothing changed in between: the stacks, the
+  /// symmetry and every site within the element's radius. This catches loops like `jump` to
+  /// self long before any step budget would. `None` turns it off, the default.
+  pub fn set_loop_limit(&mut self, limit: Option<usize>) {
+    self.loop_limit = limit;
+    self.visits.clear();
+  }
+
+  /// Hashes everything an instruction can observe or change, apart from random numbers.
+  fn state_hash<T: mfm::EventWindow>(&self, ew: &T) -> u64 {
+    let mut h = DefaultHasher::new();
+    self.op_stack.hash(&mut h);
+    self.call_stack.hash(&mut h);
+    self.symmetry.bits().hash(&mut h);
+    for s in self.symmetries_stack.iter() {
+      s.bits().hash(&mut h);
+    }
+    for i in 0..base::window_size(self.radius) {
+      ew.get(i).hash(&mut h);
+      ew.get_paint_at(i).bits().hash(&mut h);
+    }
+    h.finish()
+  }
+
+  /// Encodes the execution state so a paused event can be resumed later, possibly in another
+  /// process: the instruction pointer, the symmetry and all three stacks.
+  ///
+  /// The layout is big-endian: `u32` ip, `u8` symmetry, then each of the symmetries, call and
+  /// op stacks as a `u16` count followed by its entries (`u8`, `u32` and a `u8` sign tag plus
+  /// `u128` bits respectively). Stack limits and loop detection are not part of the state.
+  pub fn serialize(&self) -> Vec<u8> {
+    let mut b = Vec::new();
+    b.extend_from_slice(&(self.ip as u32).to_be_bytes());
+    b.push(self.symmetry.bits());
+    b.extend_from_slice(&(self.symmetries_stack.len() as u16).to_be_bytes());
+    b.extend(self.symmetries_stack.iter().map(|s| s.bits()));
+    b.extend_from_slice(&(self.call_stack.len() as u16).to_be_bytes());
+    for &ret in &self.call_stack {
+      b.extend_from_slice(&(ret as u32).to_be_bytes());
+    }
+    b.extend_from_slice(&(self.op_stack.len() as u16).to_be_bytes());
+    for c in &self.op_stack {
+      let (tag, bits) = match *c {
+        Const::Unsigned(x) => (0u8, x),
+        Const::Signed(x) => (1u8, x as u128),
+      };
+      b.push(tag);
+      b.extend_from_slice(&bits.to_be_bytes());
+    }
+    b
+  }
+
+  /// Decodes a cursor written by `serialize`. Limits and loop detection take their defaults.
+  pub fn deserialize(b: &[u8]) -> Result<Cursor, Error> {
+    let mut r = b;
+    let mut cursor = Cursor::new();
+    cursor.ip = r.read_u32::<BigEndian>()? as usize;
+    cursor.symmetry = r.read_u8()?.into();
+    for _ in 0..r.read_u16::<BigEndian>()? {
+      cursor.symmetries_stack.push(r.read_u8()?.into());
+    }
+    for _ in 0..r.read_u16::<BigEndian>()? {
+      cursor.call_stack.push(r.read_u32::<BigEndian>()? as usize);
+    }
+    for _ in 0..r.read_u16::<BigEndian>()? {
+      let tag = r.read_u8()?;
+      let bits = r.read_u128::<BigEndian>()?;
+      cursor.op_stack.push(match tag {
+        0 | 1 => Const::with_sign(bits, tag == 1),
+        _ => return Err(Error::BadConstantType(tag)),
+      });
+    }
+    if !r.is_empty() {
+      return Err(Error::TrailingCursorData(r.len()));
+    }
+    Ok(cursor)
+  }
+
+  /// Returns whether the last event ended with an explicit `exit` rather than running off the
+  /// end of its code.
+  pub fn exited(&self) -> bool {
+    self.exited
+  }
+
+  fn pop(&mut self) -> Result<Const, Error> {
+    self.op_stack.pop().ok_or_else(|| self.underflow())
+  }
+
+  /// Returns the error for popping any of the cursor's stacks when it is empty.
+  fn underflow(&self) -> Error {
+    Error::StackUnderflow {
+      ip: self.ip,
+      op: self.op,
+    }
+  }
+
+  /// Pops a divisor, failing rather than letting the division panic when it is zero.
+  fn pop_divisor(&mut self) -> Result<Const, Error> {
+    let b = self.pop()?;
+    if b.is_zero() {
+      return Err(Error::DivideByZero(self.ip));
+    }
+    Ok(b)
   }
 
-  fn pop(&mut self) -> Const {
-    self.op_stack.pop().unwrap()
+  /// Pops a site number and maps it through the current symmetry. Sites beyond the running
+  /// element's radius are an error rather than a silent read of a neighbor it did not declare.
+  fn pop_site(&mut self) -> Result<usize, Error> {
+    let i = checked_site(self.pop()?)?;
+    if i >= base::window_size(self.radius) {
+      return Err(Error::SiteOutOfRadius(i));
+    }
+    Ok(mfm::map_site(i as u8, self.symmetry) as usize)
   }
+}
+
+/// Converts `x` to a `u16`, failing rather than truncating when it is out of range.
+fn checked_u16(x: Const) -> Result<u16, Error> {
+  let v = match x {
+    Const::Unsigned(v) => u16::try_from(v).ok(),
+    Const::Signed(v) => u16::try_from(v).ok(),
+  };
+  v.ok_or(Error::OutOfRange(x))
+}
 
-  fn pop_site(&mut self) -> usize {
-    let i: u8 = self.pop().into();
-    mfm::map_site(i, self.symmetry) as usize
+/// Converts `x` to a site number, failing when it is outside the largest event window.
+fn checked_site(x: Const) -> Result<usize, Error> {
+  match checked_u16(x) {
+    Ok(i) if (i as usize) < base::window_size(4) => Ok(i as usize),
+    _ => Err(Error::BadSite(x)),
+  }
+}
+
+/// Counts the sites of type `t` within `radius` of the origin, seen through `symmetry`.
+fn count_neighbors<T: mfm::EventWindow>(
+  ew: &T,
+  radius: u8,
+  symmetry: Symmetries,
+  t: u16,
+) -> Result<u8, Error> {
+  let mut n = 0u8;
+  for i in 1..base::window_size(radius) {
+    let a = ew.get(mfm::map_site(i as u8, symmetry) as usize);
+    if checked_u16(a.apply(&FieldSelector::TYPE))? == t {
+      n += 1;
+    }
+  }
+  Ok(n)
+}
+
+/// Returns a uniform random integer in `[0, n)`, or 0 when `n` is 0.
+fn rand_below<T: mfm::Rand>(ew: &mut T, n: u128) -> u128 {
+  if n == 0 {
+    return 0;
+  }
+  if let Ok(n) = u32::try_from(n) {
+    // Reject the biased tail of the range so that each residue is equally likely.
+    let zone = u32::MAX - (u32::MAX - n + 1) % n;
+    let mut r = ew.rand_u32();
+    while r > zone {
+      r = ew.rand_u32();
+    }
+    return (r % n) as u128;
+  }
+  // Draw just enough bits to cover `n - 1` and retry past it, which rejects under half of draws.
+  let bits = 128 - (n - 1).leading_zeros();
+  let mask = u128::MAX >> (128 - bits);
+  loop {
+    let mut r = 0u128;
+    for _ in 0..bits.div_ceil(32) {
+      r = r << 32 | ew.rand_u32() as u128;
+    }
+    r &= mask;
+    if r < n {
+      return r;
+    }
+  }
+}
+
+/// Work done by `Runtime::run`, which also reports it to its progress callback.
+#[derive(Debug, Default)]
+pub struct RunStats {
+  /// Number of events which ran to completion.
+  pub events: u64,
+  /// Number of events the run was asked for.
+  pub total: u64,
+  /// Population of the event window when the stats were taken.
+  pub population: usize,
+  /// Number of site swaps performed.
+  pub swaps: usize,
+  /// Number of site writes performed.
+  pub sets: usize,
+  /// Whether the run stopped early because no atom or paint was changing.
+  pub quiescent: bool,
+  /// The error which ended the run early, if any.
+  pub error: Option<Error>,
+}
+
+/// An event window which counts the swaps and writes made through it.
+struct CountingWindow<'a, T> {
+  ew: &'a mut T,
+  swaps: usize,
+  sets: usize,
+  /// Number of swaps and writes, of atoms or paint, which actually changed a site.
+  changes: usize,
+}
+
+impl<'a, T> CountingWindow<'a, T> {
+  fn new(ew: &'a mut T) -> Self {
+    Self {
+      ew,
+      swaps: 0,
+      sets: 0,
+      changes: 0,
+    }
+  }
+}
+
+impl<T: mfm::EventWindow> mfm::EventWindow for CountingWindow<'_, T> {
+  fn reset(&mut self) {
+    self.ew.reset()
+  }
+
+  fn get(&self, i: usize) -> Const {
+    self.ew.get(i)
+  }
+
+  fn get_field(&self, i: usize, f: &FieldSelector) -> Const {
+    self.ew.get_field(i, f)
+  }
+
+  fn set(&mut self, i: usize, v: Const) {
+    self.sets += 1;
+    if !self.ew.get(i).eq_masked(&v, u128::MAX) {
+      self.changes += 1;
+    }
+    self.ew.set(i, v)
+  }
+
+  fn swap(&mut self, i: usize, j: usize) {
+    self.swaps += 1;
+    if !self.ew.get(i).eq_masked(&self.ew.get(j), u128::MAX) {
+      self.changes += 1;
+    }
+    self.ew.swap(i, j)
+  }
+
+  fn get_paint_at(&self, i: usize) -> crate::base::color::Color {
+    self.ew.get_paint_at(i)
+  }
+
+  fn set_paint_at(&mut self, i: usize, c: crate::base::color::Color) {
+    if self.ew.get_paint_at(i).bits() != c.bits() {
+      self.changes += 1;
+    }
+    self.ew.set_paint_at(i, c)
+  }
+
+  fn population(&self) -> usize {
+    self.ew.population()
+  }
+
+  fn index(&self, i: usize) -> Option<usize> {
+    self.ew.index(i)
+  }
+}
+
+impl<T: mfm::Rand> mfm::Rand for CountingWindow<'_, T> {
+  fn rand_u32(&mut self) -> u32 {
+    self.ew.rand_u32()
+  }
+
+  fn rand(&mut self) -> Const {
+    self.ew.rand()
   }
 }
 
@@ -98,10 +445,14 @@ pub struct Runtime<'input> {
   tag: Option<String>,
   pub code_map: HashMap<u16, Vec<Instruction<'input>>>,
   pub type_map: HashMap<u16, Metadata>,
+  /// Label offsets of each element, keyed by `base::name_hash` of the label name.
+  pub code_index_map: HashMap<u16, HashMap<u32, u16>>,
+  /// Reject loaded code which provably underflows the stack (see `check_stack_discipline`).
+  pub check_stack: bool,
 }
 
 impl<'input> Runtime<'input> {
-  const MINOR_VERSION: u16 = 1;
+  const MINOR_VERSION: u16 = 4;
   const MAJOR_VERSION: u16 = 0;
 
   pub fn new() -> Self {
@@ -109,7 +460,41 @@ impl<'input> Runtime<'input> {
       tag: None,
       type_map: Self::new_type_map(),
       code_map: Self::new_code_map(),
+      code_index_map: HashMap::new(),
+      check_stack: false,
+    }
+  }
+
+  /// Restores the runtime to its initial state with only Empty loaded and no bound build tag.
+  pub fn clear(&mut self) {
+    self.tag = None;
+    self.type_map = Self::new_type_map();
+    self.code_map = Self::new_code_map();
+    self.code_index_map.clear();
+  }
+
+  /// Returns the instruction offset of the label `name` in the code of element `type_num`.
+  pub fn label_offset(&self, type_num: u16, name: &str) -> Option<u16> {
+    self
+      .code_index_map
+      .get(&type_num)?
+      .get(&base::name_hash(name))
+      .copied()
+  }
+
+  /// Returns a hash of the metadata and code of all loaded elements.
+  ///
+  /// Elements are visited in type order so the hash does not depend on the order they were loaded.
+  /// The hash is `base::Fnv1a`, so it does not change between builds.
+  pub fn physics_hash(&self) -> u64 {
+    let mut types: Vec<&u16> = self.type_map.keys().collect();
+    types.sort();
+    let mut h = base::Fnv1a::default();
+    for t in types {
+      self.type_map[t].hash(&mut h);
+      self.code_map.get(t).hash(&mut h);
     }
+    h.finish()
   }
 
   fn new_type_map() -> HashMap<u16, Metadata> {
@@ -141,6 +526,12 @@ impl<'input> Runtime<'input> {
         n |= r.read_i64::<BigEndian>()? as i128;
         Ok(n.into())
       }
+      2 => Ok(r.read_u8()?.into()),
+      3 => Ok(r.read_u16::<BigEndian>()?.into()),
+      4 => Ok(r.read_u32::<BigEndian>()?.into()),
+      5 => Ok(r.read_i8()?.into()),
+      6 => Ok(r.read_i16::<BigEndian>()?.into()),
+      7 => Ok(r.read_i32::<BigEndian>()?.into()),
       i => Err(Error::BadConstantType(i)),
     }
   }
@@ -176,11 +567,29 @@ impl<'input> Runtime<'input> {
         let c = Self::read_const(r)?;
         elem.parameter_map.insert(i, c);
       }
+      11 => elem.wrapping = r.read_u8()? != 0, // Wrapping
       i => return Err(Error::BadMetadataOpCode(i)),
     }
     Ok(())
   }
 
+  /// Rejects metadata which would break the element when printed or run: a missing name, a radius
+  /// past `base::MAX_RADIUS`, or a symbol other than one printable grapheme. A grapheme may span
+  /// several chars, as with "❤️" or a flag.
+  fn check_metadata(elem: &Metadata) -> Result<(), Error> {
+    if elem.name.is_empty() {
+      return Err(Error::MissingName);
+    }
+    if elem.radius > base::MAX_RADIUS {
+      return Err(Error::BadRadius(elem.radius));
+    }
+    let mut graphemes = elem.symbol.graphemes(true);
+    match (graphemes.next(), graphemes.next()) {
+      (Some(g), None) if !g.chars().any(char::is_control) => Ok(()),
+      _ => Err(Error::BadSymbol(elem.symbol.clone())),
+    }
+  }
+
   fn read_instruction<R: ReadBytesExt>(
     r: &mut R,
     code: &mut Vec<Instruction<'input>>,
@@ -278,24 +687,136 @@ impl<'input> Runtime<'input> {
       88 => Instruction::SetPaint,
       89 => Instruction::GetPaint,
       90 => Instruction::Rand,
+      91 => Instruction::NeighborCount(Arg::Runtime(r.read_u16::<BigEndian>()?)),
+      92 => Instruction::RandRange,
+      93 => Instruction::AtomEqual,
+      94 => Instruction::Compare,
+      95 => Instruction::SetSignedField(Arg::Runtime(r.read_u16::<BigEndian>()?.into())),
+      96 => Instruction::SetSignedSiteField(Arg::Runtime(r.read_u16::<BigEndian>()?.into())),
+      97 => Instruction::GetParameterIndex(Arg::Runtime(r.read_u16::<BigEndian>()?)),
+      98 => Instruction::SiteIs(Arg::Runtime(r.read_u16::<BigEndian>()?)),
+      99 => Instruction::IsEmpty,
+      100 => Instruction::Begin,
+      101 => Instruction::Commit,
+      102 => Instruction::Abort,
+      103 => Instruction::GetParameterOf(Arg::Runtime(r.read_u32::<BigEndian>()?)),
+      104 => Instruction::SiteToXY,
+      105 => Instruction::XYToSite,
+      106 => Instruction::ModEuclid,
+      107 => Instruction::GetSitePaint,
+      108 => Instruction::SetSitePaint,
+      109 => Instruction::CountType,
       i => return Err(Error::BadInstructionOpCode(i)),
     };
     code.push(instr);
     Ok(())
   }
 
-  pub fn load_from_reader<R: ReadBytesExt>(&mut self, r: &mut R) -> Result<mfm::Metadata, Error> {
-    {
-      let v = r.read_u32::<BigEndian>()?;
-      if v != MAGIC_NUMBER {
-        return Err(Error::BadMagicNumber(v));
+  /// Checks that no instruction can be reached with fewer values on the stack than it pops,
+  /// however it is reached (see `ast::check_stack_depth`).
+  pub fn check_stack_discipline(code: &[Instruction<'input>]) -> Result<(), Error> {
+    let target = |ip: usize| match code[ip] {
+      Instruction::Jump(x)
+      | Instruction::JumpZero(x)
+      | Instruction::JumpNonZero(x)
+      | Instruction::Call(x) => Some(*x.runtime() as usize),
+      _ => None,
+    };
+    ast::check_stack_depth(code, target, false).map_err(|f| Error::StackDiscipline(f.ip))
+  }
+
+  /// Loads the elements of another compiled archive alongside those already loaded and returns
+  /// the old to new type number of each one.
+  ///
+  /// Type numbers already taken here are reassigned to the lowest free numbers from
+  /// `renumber_from`, and the type operands of `gettype`, `neighborcount` and `siteis` in the
+  /// merged code are rewritten to match. The other archive may have a different build tag.
+  pub fn merge(
+    &mut self,
+    other_archive: &[u8],
+    renumber_from: u16,
+  ) -> Result<HashMap<u16, u16>, Error> {
+    let mut other = Runtime::new();
+    other.check_stack = self.check_stack;
+    other.load_all_from_reader(&mut &other_archive[..])?;
+    other.type_map.remove(&0);
+
+    let mut olds: Vec<u16> = other.type_map.keys().copied().collect();
+    olds.sort_unstable();
+    let mut mapping = HashMap::new();
+    // `None` once `u16::MAX` is taken, which is only an error if another number is needed.
+    let mut next = Some(renumber_from);
+    for &old in &olds {
+      if !self.type_map.contains_key(&old) {
+        mapping.insert(old, old);
+        continue;
+      }
+      let mut new = next.ok_or(Error::TypeNumbersExhausted)?;
+      while self.type_map.contains_key(&new) || other.type_map.contains_key(&new) {
+        new = new.checked_add(1).ok_or(Error::TypeNumbersExhausted)?;
       }
+      mapping.insert(old, new);
+      next = new.checked_add(1);
     }
-    {
-      let v = r.read_u16::<BigEndian>()?;
-      if v != Self::MINOR_VERSION {
-        return Err(Error::BadMinorVersion(v));
+
+    for old in olds {
+      let new = mapping[&old];
+      let mut elem = other.type_map.remove(&old).unwrap();
+      elem.type_num = new;
+      let mut code = other.code_map.remove(&old).unwrap();
+      for op in code.iter_mut() {
+        match op {
+          Instruction::GetType(Arg::Runtime(t))
+          | Instruction::NeighborCount(Arg::Runtime(t))
+          | Instruction::SiteIs(Arg::Runtime(t)) => {
+            *t = mapping.get(t).copied().unwrap_or(*t);
+          }
+          _ => {}
+        }
       }
+      self.type_map.insert(new, elem);
+      self.code_map.insert(new, code);
+      if let Some(index) = other.code_index_map.remove(&old) {
+        self.code_index_map.insert(new, index);
+      }
+    }
+    Ok(mapping)
+  }
+
+  /// Loads every element in a stream of concatenated elements, such as a multi-element file, and
+  /// returns their metadata in order.
+  pub fn load_all_from_reader<R: io::BufRead>(
+    &mut self,
+    r: &mut R,
+  ) -> Result<Vec<mfm::Metadata>, Error> {
+    let mut elems = Vec::new();
+    while !r.fill_buf()?.is_empty() {
+      match r.read_u32::<BigEndian>() {
+        Ok(MAGIC_NUMBER) => elems.push(self.load_element(r)?),
+        // Anything but another element after the first is not ours to skip.
+        _ if !elems.is_empty() => return Err(Error::TrailingData),
+        Ok(v) => return Err(Error::BadMagicNumber(v)),
+        Err(e) => return Err(e.into()),
+      }
+    }
+    Ok(elems)
+  }
+
+  pub fn load_from_reader<R: ReadBytesExt>(&mut self, r: &mut R) -> Result<mfm::Metadata, Error> {
+    let v = r.read_u32::<BigEndian>()?;
+    if v != MAGIC_NUMBER {
+      return Err(Error::BadMagicNumber(v));
+    }
+    self.load_element(r)
+  }
+
+  /// Loads the rest of an element whose magic number has been read.
+  fn load_element<R: ReadBytesExt>(&mut self, r: &mut R) -> Result<mfm::Metadata, Error> {
+    // Older minor versions are a subset of the current format: version 1 lacks the code index,
+    // versions before 3 only use full width constants and versions before 4 lack signed fields.
+    let minor = r.read_u16::<BigEndian>()?;
+    if minor == 0 || minor > Self::MINOR_VERSION {
+      return Err(Error::BadMinorVersion(minor));
     }
     {
       let v = r.read_u16::<BigEndian>()?;
@@ -319,35 +840,164 @@ impl<'input> Runtime<'input> {
     let mut elem = Metadata::new();
     elem.type_num = type_num;
 
+    elem.name.clear();
     for _ in 0..r.read_u8()? {
       Self::read_metadata(r, &mut elem)?;
     }
+    Self::check_metadata(&elem)?;
 
     trace!("{:?}", elem);
 
+    let mut code_index = HashMap::new();
+    let code_index_size = if minor >= 2 {
+      r.read_u16::<BigEndian>()?
+    } else {
+      0
+    };
+    for _ in 0..code_index_size {
+      let hash = r.read_u32::<BigEndian>()?;
+      code_index.insert(hash, r.read_u16::<BigEndian>()?);
+    }
+
     let mut code = Vec::new();
 
-    for _ in 0..r.read_u16::<BigEndian>()? {
-      Self::read_instruction(r, &mut code)?;
+    let code_lines = r.read_u16::<BigEndian>()?;
+    for i in 0..code_lines {
+      match Self::read_instruction(r, &mut code) {
+        Err(Error::IOError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+          return Err(Error::TruncatedCode {
+            want: code_lines,
+            got: i,
+          })
+        }
+        res => res?,
+      }
     }
 
     trace!("{:?}", code);
 
+    if self.check_stack {
+      Self::check_stack_discipline(&code)?;
+    }
+
     self.type_map.insert(type_num, elem.clone());
     self.code_map.insert(type_num, code);
+    self.code_index_map.insert(type_num, code_index);
     Ok(elem)
   }
 
+  /// Runs one event on a copy of `window` under each single symmetry, from R000L to R270R, and
+  /// returns the resulting windows.
+  ///
+  /// Each run draws from a fresh generator seeded with `seed`. A symmetry-correct element produces
+  /// outputs which are the corresponding rotations and reflections of each other.
+  pub fn run_all_symmetries(
+    &self,
+    window: &[Const; 41],
+    seed: u64,
+  ) -> Result<[[Const; 41]; 8], Error> {
+    let mut out = [[Const::Unsigned(0); 41]; 8];
+    for (k, o) in out.iter_mut().enumerate() {
+      let mut rng = SmallRng::seed_from_u64(seed);
+      let mut ew = mfm::MinimalEventWindow::new(&mut rng);
+      for (i, a) in window.iter().enumerate() {
+        ew.set(i, *a);
+      }
+      let mut cursor = Cursor::with_symmetry(Symmetries::from(1u8 << k));
+      Self::execute(&mut ew, &mut cursor, &self.code_map, &self.type_map)?;
+      for (i, a) in o.iter_mut().enumerate() {
+        *a = ew.get(i);
+      }
+    }
+    Ok(out)
+  }
+
+  /// Resets `cursor` for an event at the current window center, selecting its symmetry from the
+  /// default symmetries of the element there. Empty and unknown elements use R000L.
+  pub fn reset_cursor<T: mfm::EventWindow + mfm::Rand>(&self, ew: &mut T, cursor: &mut Cursor) {
+    let t = checked_u16(ew.get(0).apply(&FieldSelector::TYPE)).unwrap_or(0);
+    let symmetries = self
+      .type_map
+      .get(&t)
+      .map(|m| m.symmetries)
+      .unwrap_or_else(Symmetries::empty);
+    cursor.reset(mfm::select_symmetries(ew.rand_u32(), symmetries));
+  }
+
+  /// Runs up to `total` events, resetting the event window and the cursor with `reset_cursor`
+  /// between each, so every element runs under its own symmetries. `progress` is called with the
+  /// window and the stats so far after every `interval` events.
+  ///
+  /// The run stops at the first error, which is returned in the stats along with the number of
+  /// events completed before it. With a positive `quiescence` it also stops once that many events
+  /// in a row, not counting those centered on an empty site, have changed no atom or paint.
+  /// Leaving out empty sites scales the window by the area of a sparsely populated grid over its
+  /// population, so one busy atom among many empty sites keeps the run going. An atom which only
+  /// rarely changes anything may still be taken for quiet.
+  pub fn run<T: mfm::EventWindow + mfm::Rand, F: FnMut(&T, &RunStats)>(
+    &self,
+    ew: &mut T,
+    cursor: &mut Cursor,
+    total: u64,
+    interval: u64,
+    quiescence: u64,
+    mut progress: F,
+  ) -> RunStats {
+    let mut ew = CountingWindow::new(ew);
+    let mut stats = RunStats {
+      total,
+      ..RunStats::default()
+    };
+    let mut quiet = 0;
+    while stats.events < total {
+      let changes = ew.changes;
+      let empty = ew.get(0).apply(&FieldSelector::TYPE).is_zero();
+      if let Err(e) = Self::execute(&mut ew, cursor, &self.code_map, &self.type_map) {
+        stats.error = Some(e);
+        break;
+      }
+      stats.events += 1;
+      ew.reset();
+      self.reset_cursor(&mut ew, cursor);
+      if interval > 0 && stats.events.is_multiple_of(interval) {
+        stats.population = ew.population();
+        stats.swaps = ew.swaps;
+        stats.sets = ew.sets;
+        progress(ew.ew, &stats);
+      }
+      if ew.changes != changes {
+        quiet = 0;
+      } else if !empty {
+        quiet += 1;
+      }
+      if quiescence > 0 && quiet >= quiescence {
+        stats.quiescent = true;
+        break;
+      }
+    }
+    stats.population = ew.population();
+    stats.swaps = ew.swaps;
+    stats.sets = ew.sets;
+    stats
+  }
+
   pub fn execute<T: mfm::EventWindow + mfm::Rand>(
     ew: &mut T,
     cursor: &mut Cursor,
     code_map: &HashMap<u16, Vec<Instruction<'input>>>,
+    type_map: &HashMap<u16, Metadata>,
   ) -> Result<(), Error> {
     let my_atom = ew.get(0);
-    let my_type: u16 = my_atom.apply(&FieldSelector::TYPE).into();
+    let my_type = checked_u16(my_atom.apply(&FieldSelector::TYPE))?;
     let code = code_map
       .get(&my_type)
       .ok_or(Error::UnknownElement(my_type))?;
+    let my_meta = type_map
+      .get(&my_type)
+      .ok_or(Error::UnknownElement(my_type))?;
+    cursor.radius = my_meta.radius;
+    // Writes still buffered when the event ends are discarded along with the window.
+    let ew = &mut mfm::DeferredWindow::new(ew);
     loop {
       if cursor.ip >= code.len() {
         // Handle implicit Ret:
@@ -366,65 +1016,142 @@ impl<'input> Runtime<'input> {
           break;
         }
       }
+      if cursor.op_stack.len() > cursor.max_stack_depth {
+        return Err(Error::StackOverflow);
+      }
+      if let Some(limit) = cursor.loop_limit {
+        let state = cursor.state_hash(ew);
+        let v = cursor.visits.entry(cursor.ip).or_insert((state, 0));
+        if v.0 == state {
+          v.1 += 1;
+        } else {
+          *v = (state, 1);
+        }
+        if v.1 > limit {
+          return Err(Error::NoProgress(cursor.ip));
+        }
+      }
       let op = code[cursor.ip];
+      cursor.op = op.mnemonic();
       trace!("{:?} => {:?}", cursor, op);
       match op {
         Instruction::Nop => {}
-        Instruction::Exit => break,
+        Instruction::Exit => {
+          cursor.exited = true;
+          break;
+        }
         Instruction::SwapSites => {
-          let j: usize = cursor.pop_site();
-          let i: usize = cursor.pop_site();
+          let j: usize = cursor.pop_site()?;
+          let i: usize = cursor.pop_site()?;
           ew.swap(i, j);
         }
         Instruction::SetSite => {
-          let c = cursor.pop();
-          let i: usize = cursor.pop_site();
+          let c = cursor.pop()?;
+          let i: usize = cursor.pop_site()?;
           ew.set(i, c);
         }
         Instruction::SetField(f) => {
-          let c = cursor.pop();
-          let mut a = cursor.pop();
+          let c = cursor.pop()?;
+          let mut a = cursor.pop()?;
           let fi = f.runtime();
           a.store(c, fi);
           cursor.op_stack.push(a);
         }
         Instruction::SetSiteField(f) => {
-          let c = cursor.pop();
-          let i: usize = cursor.pop_site();
+          let c = cursor.pop()?;
+          let i: usize = cursor.pop_site()?;
           let fi = f.runtime();
           let mut a = ew.get(i);
           a.store(c, fi);
           ew.set(i, a);
         }
         Instruction::GetSite => {
-          let v = ew.get(cursor.pop_site());
+          let v = ew.get(cursor.pop_site()?);
           cursor.op_stack.push(v);
         }
         Instruction::GetField(f) => {
-          let a = cursor.pop();
+          let a = cursor.pop()?;
           cursor.op_stack.push(a.apply(f.runtime()));
         }
         Instruction::GetSiteField(f) => {
-          let i: usize = cursor.pop_site();
-          cursor.op_stack.push(ew.get(i).apply(f.runtime()));
+          let i: usize = cursor.pop_site()?;
+          cursor.op_stack.push(ew.get_field(i, f.runtime()));
         }
         Instruction::GetSignedField(f) => {
-          let i: i128 = cursor.pop().apply(f.runtime()).into();
-          cursor.op_stack.push(i.into());
+          let a = cursor.pop()?.apply_signed(f.runtime());
+          cursor.op_stack.push(a);
         }
         Instruction::GetSignedSiteField(f) => {
-          let i: usize = cursor.pop_site();
-          let i: i128 = ew.get(i).apply(f.runtime()).into();
-          cursor.op_stack.push(i.into());
+          let i: usize = cursor.pop_site()?;
+          cursor.op_stack.push(ew.get(i).apply_signed(f.runtime()));
         }
         Instruction::GetType(x) => cursor.op_stack.push((*x.runtime()).into()),
         Instruction::GetParameter(c) => {
           cursor.op_stack.push(*c.runtime());
         }
+        Instruction::GetParameterIndex(x) => {
+          let i = *x.runtime() as usize + checked_u16(cursor.pop()?)? as usize;
+          let (_, c) = my_meta
+            .parameter_map
+            .get_index(i)
+            .ok_or(Error::BadParameterIndex(i))?;
+          cursor.op_stack.push(*c);
+        }
+        Instruction::SiteIs(x) => {
+          let i = cursor.pop_site()?;
+          // Sites off the grid hold nothing, not even Empty.
+          let is = ew.index(i).is_some()
+            && checked_u16(ew.get_field(i, &FieldSelector::TYPE))? == *x.runtime();
+          cursor.op_stack.push((is as u8).into());
+        }
+        Instruction::IsEmpty => {
+          let i = cursor.pop_site()?;
+          let is = ew.index(i).is_some() && ew.get_field(i, &FieldSelector::TYPE).is_zero();
+          cursor.op_stack.push((is as u8).into());
+        }
+        Instruction::GetParameterOf(x) => {
+          let t = checked_u16(cursor.pop()?)?;
+          let c = type_map
+            .get(&t)
+            .and_then(|m| {
+              m.parameter_map
+                .iter()
+                .find(|(k, _)| base::name_hash(k) == *x.runtime())
+            })
+            .map_or(0u8.into(), |(_, c)| *c);
+          cursor.op_stack.push(c);
+        }
+        // Site numbers and offsets are both in the element's own frame, so no symmetry applies.
+        Instruction::SiteToXY => {
+          let (x, y) = mfm::window_offsets()[checked_site(cursor.pop()?)?];
+          cursor.op_stack.push(Const::Signed(x as i128));
+          cursor.op_stack.push(Const::Signed(y as i128));
+        }
+        Instruction::XYToSite => {
+          let y = i128::from(cursor.pop()?);
+          let x = i128::from(cursor.pop()?);
+          let r = -4..=4;
+          let site = if r.contains(&x) && r.contains(&y) {
+            mfm::site_at(x as isize, y as isize)
+          } else {
+            None
+          };
+          cursor
+            .op_stack
+            .push(site.map_or(Const::Signed(-1), |s| s.into()));
+        }
+        Instruction::Begin => ew.begin()?,
+        Instruction::Commit => ew.commit()?,
+        Instruction::Abort => ew.abort()?,
         Instruction::Scan => todo!(),
         Instruction::SaveSymmetries => cursor.symmetries_stack.push(cursor.symmetry),
         Instruction::UseSymmetries(x) => cursor.symmetry = mfm::select_symmetries(ew.rand_u32(), x),
-        Instruction::RestoreSymmetries => cursor.symmetry = cursor.symmetries_stack.pop().unwrap(),
+        Instruction::RestoreSymmetries => {
+          cursor.symmetry = cursor
+            .symmetries_stack
+            .pop()
+            .ok_or_else(|| cursor.underflow())?
+        }
         Instruction::Push0 => cursor.op_stack.push(0u8.into()),
         Instruction::Push1 => cursor.op_stack.push(1u8.into()),
         Instruction::Push2 => cursor.op_stack.push(2u8.into()),
@@ -468,34 +1195,39 @@ impl<'input> Runtime<'input> {
         Instruction::Push40 => cursor.op_stack.push(40u8.into()),
         Instruction::Push(c) => cursor.op_stack.push(c),
         Instruction::Pop => {
-          cursor.op_stack.pop().expect("stack underflow");
+          cursor.pop()?;
         }
         Instruction::Dup => {
-          let t = cursor.pop();
+          let t = cursor.pop()?;
           cursor.op_stack.push(t);
           cursor.op_stack.push(t);
         }
         Instruction::Over => {
-          let n = cursor.op_stack.len();
-          let a = cursor.op_stack[n - 2];
-          cursor.op_stack.push(a);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.op_stack.extend_from_slice(&[a, b, a]);
         }
         Instruction::Swap => {
-          let n = cursor.op_stack.len();
-          cursor.op_stack.swap(n - 2, n - 1);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.op_stack.extend_from_slice(&[b, a]);
         }
         Instruction::Rot => {
-          let n = cursor.op_stack.len();
-          cursor.op_stack.swap(n - 2, n - 1);
-          cursor.op_stack.swap(n - 3, n - 2);
+          let c = cursor.pop()?;
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.op_stack.extend_from_slice(&[c, a, b]);
         }
         Instruction::Call(x) => {
+          if cursor.call_stack.len() >= cursor.max_call_depth {
+            return Err(Error::CallDepthExceeded);
+          }
           cursor.call_stack.push(cursor.ip);
           cursor.ip = *x.runtime() as usize;
           continue;
         }
         Instruction::Ret => {
-          cursor.ip = cursor.call_stack.pop().unwrap();
+          cursor.ip = cursor.call_stack.pop().ok_or_else(|| cursor.underflow())?;
           if cursor.ip == u16::MAX as usize {
             break;
           }
@@ -504,84 +1236,101 @@ impl<'input> Runtime<'input> {
         }
         Instruction::Checksum => todo!(),
         Instruction::Add => {
-          let b = cursor.pop();
-          let a = cursor.pop();
-          cursor.op_stack.push(a + b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.op_stack.push(if my_meta.wrapping {
+            a.wrapping_add(b)
+          } else {
+            a + b
+          });
         }
         Instruction::Sub => {
-          let b = cursor.pop();
-          let a = cursor.pop();
-          cursor.op_stack.push(a - b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.op_stack.push(if my_meta.wrapping {
+            a.wrapping_sub(b)
+          } else {
+            a - b
+          });
         }
         Instruction::Neg => {
-          let a = cursor.pop();
+          let a = cursor.pop()?;
           cursor.op_stack.push(-a);
         }
         Instruction::Mod => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop_divisor()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(a % b);
         }
+        Instruction::ModEuclid => {
+          let b = cursor.pop_divisor()?;
+          let a = cursor.pop()?;
+          cursor.op_stack.push(a.rem_euclid(b));
+        }
         Instruction::Mul => {
-          let b = cursor.pop();
-          let a = cursor.pop();
-          cursor.op_stack.push(a * b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.op_stack.push(if my_meta.wrapping {
+            a.wrapping_mul(b)
+          } else {
+            a * b
+          });
         }
         Instruction::Div => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop_divisor()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(a / b);
         }
         Instruction::Less => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(if a < b { 1 } else { 0 }.into());
         }
         Instruction::LessEqual => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(if a <= b { 1 } else { 0 }.into());
         }
         Instruction::Or => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(a | b);
         }
         Instruction::And => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(a & b);
         }
         Instruction::Xor => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(a ^ b);
         }
         Instruction::Equal => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(if a == b { 1 } else { 0 }.into())
         }
         Instruction::BitCount => {
-          let a = cursor.pop();
+          let a = cursor.pop()?;
           cursor.op_stack.push(a.count_ones().into());
         }
         Instruction::BitScanForward => {
-          let a = cursor.pop();
+          let a = cursor.pop()?;
           cursor.op_stack.push(a.bitscanforward().into());
         }
         Instruction::BitScanReverse => {
-          let a = cursor.pop();
+          let a = cursor.pop()?;
           cursor.op_stack.push(a.bitscanreverse().into());
         }
         Instruction::LShift => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(a >> b.into()) // TODO handle b overflow
         }
         Instruction::RShift => {
-          let b = cursor.pop();
-          let a = cursor.pop();
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
           cursor.op_stack.push(a << b.into()) // TODO handle b overflow
         }
         Instruction::Jump(x) => {
@@ -589,51 +1338,1193 @@ impl<'input> Runtime<'input> {
           continue;
         }
         Instruction::JumpRelativeOffset => {
-          let a = cursor.pop();
-          assert!(!a.is_zero());
-          match a {
-            Const::Unsigned(x) => cursor.ip += x as usize,
-            Const::Signed(_) => {
-              let amount = a.abs();
-              if amount.is_neg() {
-                if let Some(ip) = cursor.ip.checked_sub(amount.into()) {
-                  cursor.ip = ip;
-                } else {
-                  cursor.ip = u16::MAX as usize;
-                  continue;
-                }
+          let a = cursor.pop()?;
+          // A zero offset falls through to the next instruction.
+          if !a.is_zero() {
+            let n = usize::try_from(u128::from(a.abs())).ok();
+            if a.is_neg() {
+              if let Some(ip) = n.and_then(|n| cursor.ip.checked_sub(n)) {
+                cursor.ip = ip;
               } else {
-                cursor.ip = cursor.ip.saturating_add(amount.into());
+                cursor.ip = u16::MAX as usize;
+                continue;
               }
+            } else if let Some(ip) = n
+              .and_then(|n| cursor.ip.checked_add(n))
+              .filter(|ip| *ip < code.len())
+            {
+              cursor.ip = ip;
+            } else {
+              // Jumping past the end of the code exits.
+              break;
             }
+            continue;
           }
-          continue;
         }
         Instruction::JumpZero(x) => {
-          if cursor.pop().is_zero() {
+          if cursor.pop()?.is_zero() {
             cursor.ip = *x.runtime() as usize;
             continue;
           }
         }
         Instruction::JumpNonZero(x) => {
-          if !cursor.pop().is_zero() {
+          if !cursor.pop()?.is_zero() {
             cursor.ip = *x.runtime() as usize;
             continue;
           }
         }
         Instruction::SetPaint => {
-          let c: u32 = cursor.pop().into();
+          let c: u32 = cursor.pop()?.into();
           ew.set_paint(c.into());
         }
         Instruction::GetPaint => {
           cursor.op_stack.push(ew.get_paint().bits().into());
         }
+        Instruction::GetSitePaint => {
+          let i = cursor.pop_site()?;
+          cursor.op_stack.push(ew.get_paint_at(i).bits().into());
+        }
+        Instruction::SetSitePaint => {
+          let c: u32 = cursor.pop()?.into();
+          let i = cursor.pop_site()?;
+          ew.set_paint_at(i, c.into());
+        }
         Instruction::Rand => {
           cursor.op_stack.push(ew.rand());
         }
+        Instruction::NeighborCount(x) => {
+          let n = count_neighbors(ew, my_meta.radius, cursor.symmetry, *x.runtime())?;
+          cursor.op_stack.push(n.into());
+        }
+        Instruction::CountType => {
+          let t = checked_u16(cursor.pop()?)?;
+          let n = count_neighbors(ew, my_meta.radius, cursor.symmetry, t)?;
+          cursor.op_stack.push(n.into());
+        }
+        Instruction::RandRange => {
+          let c = cursor.pop()?;
+          let n = match c {
+            Const::Unsigned(n) => n,
+            Const::Signed(n) if n >= 0 => n as u128,
+            _ => return Err(Error::OutOfRange(c)),
+          };
+          cursor.op_stack.push(rand_below(ew, n).into());
+        }
+        Instruction::AtomEqual => {
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          let mask = !FieldSelector::CHECKSUM.mask();
+          cursor
+            .op_stack
+            .push(if a.eq_masked(&b, mask) { 1 } else { 0 }.into())
+        }
+        Instruction::Compare => {
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.op_stack.push(Const::Signed(a.cmp(&b) as i128))
+        }
+        Instruction::SetSignedField(f) => {
+          let c = cursor.pop()?;
+          let mut a = cursor.pop()?;
+          a.store_signed(c, f.runtime());
+          cursor.op_stack.push(a);
+        }
+        Instruction::SetSignedSiteField(f) => {
+          let c = cursor.pop()?;
+          let i: usize = cursor.pop_site()?;
+          let mut a = ew.get(i);
+          a.store_signed(c, f.runtime());
+          ew.set(i, a);
+        }
       }
       cursor.ip += 1;
     }
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ast;
+  use crate::ast::Node;
+  use crate::base::DataType;
+  use byteorder::WriteBytesExt;
+  use mfm::MinimalEventWindow;
+  use rand::rngs::mock::StepRng;
+  use rand::rngs::SmallRng;
+  use rand::SeedableRng;
+
+  fn element_bytes(tag: &str, type_num: u16, code_lines: u16, code: &[u8]) -> Vec<u8> {
+    element_bytes_with(tag, type_num, &[&[0, 1, b'A']], code_lines, code)
+  }
+
+  /// Like `element_bytes`, with each of `metadata` written as one encoded metadata entry.
+  fn element_bytes_with(
+    tag: &str,
+    type_num: u16,
+    metadata: &[&[u8]],
+    code_lines: u16,
+    code: &[u8],
+  ) -> Vec<u8> {
+    let mut v = Vec::new();
+    v.write_u32::<BigEndian>(MAGIC_NUMBER).unwrap();
+    v.write_u16::<BigEndian>(Runtime::MINOR_VERSION).unwrap();
+    v.write_u16::<BigEndian>(Runtime::MAJOR_VERSION).unwrap();
+    v.write_u8(tag.len() as u8).unwrap();
+    v.extend_from_slice(tag.as_bytes());
+    v.write_u16::<BigEndian>(type_num).unwrap();
+    v.write_u8(metadata.len() as u8).unwrap();
+    for m in metadata.iter() {
+      v.extend_from_slice(m);
+    }
+    v.write_u16::<BigEndian>(0).unwrap();
+    v.write_u16::<BigEndian>(code_lines).unwrap();
+    v.extend_from_slice(code);
+    v
+  }
+
+  #[test]
+  fn test_minor_version_1() {
+    let mut v = element_bytes("a", 1, 1, &[58, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7]);
+    v[5] = 1;
+    // Version 1 has no code index.
+    v.drain(16..18);
+    let mut runtime = Runtime::new();
+    runtime.load_from_reader(&mut v.as_slice()).unwrap();
+    assert!(matches!(
+      runtime.code_map[&1][..],
+      [Instruction::Push(Const::Unsigned(7))]
+    ));
+    v[5] = Runtime::MINOR_VERSION as u8 + 1;
+    assert!(matches!(
+      runtime.load_from_reader(&mut v.as_slice()),
+      Err(Error::BadMinorVersion(_))
+    ));
+  }
+
+  #[test]
+  fn test_check_metadata() {
+    let load = |metadata: &[&[u8]]| {
+      Runtime::new().load_from_reader(&mut element_bytes_with("a", 1, metadata, 1, &[0]).as_slice())
+    };
+    let name: &[u8] = &[0, 1, b'A'];
+    let meta = load(&[name, &[1, 2, 0xc3, 0xa9], &[5, 4]]).unwrap();
+    assert_eq!(meta.symbol, "é");
+    assert_eq!(meta.radius, 4);
+    for &s in &["❤️", "🇫🇷", "e\u{301}"] {
+      let mut symbol = vec![1, s.len() as u8];
+      symbol.extend_from_slice(s.as_bytes());
+      assert_eq!(load(&[name, &symbol]).unwrap().symbol, s);
+    }
+    assert!(matches!(load(&[]), Err(Error::MissingName)));
+    assert!(matches!(load(&[&[0, 0]]), Err(Error::MissingName)));
+    assert!(matches!(load(&[name, &[5, 5]]), Err(Error::BadRadius(5))));
+    assert!(matches!(
+      load(&[name, &[1, 2, b'a', b'b']]),
+      Err(Error::BadSymbol(s)) if s == "ab"
+    ));
+    assert!(matches!(load(&[name, &[1, 0]]), Err(Error::BadSymbol(_))));
+    assert!(matches!(
+      load(&[name, &[1, 1, b'\n']]),
+      Err(Error::BadSymbol(_))
+    ));
+  }
+
+  #[test]
+  fn test_clear() {
+    let mut runtime = Runtime::new();
+    runtime
+      .load_from_reader(&mut element_bytes("a", 1, 1, &[0]).as_slice())
+      .unwrap();
+    assert!(matches!(
+      runtime.load_from_reader(&mut element_bytes("b", 2, 1, &[0]).as_slice()),
+      Err(Error::BuildTagMismatch { .. })
+    ));
+    runtime.clear();
+    assert_eq!(runtime.type_map.len(), 1);
+    assert_eq!(runtime.type_map[&0].name, "Empty");
+    assert_eq!(runtime.code_map.len(), 1);
+    runtime
+      .load_from_reader(&mut element_bytes("b", 2, 1, &[0]).as_slice())
+      .unwrap();
+    assert!(runtime.type_map.contains_key(&2));
+  }
+
+  #[test]
+  fn test_truncated_code() {
+    let mut runtime = Runtime::new();
+    assert!(matches!(
+      runtime.load_from_reader(&mut element_bytes("a", 1, 2, &[0]).as_slice()),
+      Err(Error::TruncatedCode { want: 2, got: 1 })
+    ));
+    // Jump with only half of its operand.
+    assert!(matches!(
+      runtime.load_from_reader(&mut element_bytes("a", 1, 1, &[84, 0]).as_slice()),
+      Err(Error::TruncatedCode { want: 1, got: 0 })
+    ));
+  }
+
+  #[test]
+  fn test_trailing_data() {
+    let mut bin = element_bytes("a", 1, 1, &[0]);
+    bin.extend(element_bytes("a", 2, 1, &[0]));
+    let mut runtime = Runtime::new();
+    assert_eq!(
+      runtime
+        .load_all_from_reader(&mut bin.as_slice())
+        .unwrap()
+        .len(),
+      2
+    );
+    bin.extend_from_slice(&[0xde, 0xad]);
+    assert!(matches!(
+      runtime.load_all_from_reader(&mut bin.as_slice()),
+      Err(Error::TrailingData)
+    ));
+    assert!(matches!(
+      runtime.load_all_from_reader(&mut &[0xde, 0xad, 0xbe, 0xef][..]),
+      Err(Error::BadMagicNumber(0xdeadbeef))
+    ));
+  }
+
+  fn test_runtime(radius: u8, code: Vec<Instruction<'static>>) -> Runtime<'static> {
+    let mut runtime = Runtime::new();
+    let mut elem = Metadata::new();
+    elem.type_num = 1;
+    elem.radius = radius;
+    runtime.type_map.insert(1, elem);
+    runtime.code_map.insert(1, code);
+    runtime
+  }
+
+  #[test]
+  fn test_get_parameter_index() {
+    let mut runtime = test_runtime(0, vec![Instruction::GetParameterIndex(Arg::Runtime(1))]);
+    let params = &mut runtime.type_map.get_mut(&1).unwrap().parameter_map;
+    params.insert("X".to_string(), Const::Unsigned(1));
+    for i in 0..4u8 {
+      params.insert(format!("P{}", i), Const::Unsigned(10 * i as u128));
+    }
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    cursor.op_stack.push(Const::Unsigned(1));
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(cursor.op_stack, vec![Const::Unsigned(10)]);
+
+    let mut cursor = Cursor::new();
+    cursor.op_stack.push(Const::Unsigned(4));
+    assert!(matches!(
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+      Err(Error::BadParameterIndex(5))
+    ));
+  }
+
+  #[test]
+  fn test_checked_casts() {
+    assert_eq!(
+      checked_u16(Const::Unsigned(u16::MAX as u128)).unwrap(),
+      u16::MAX
+    );
+    assert!(matches!(
+      checked_u16(Const::Unsigned(1 << 16)),
+      Err(Error::OutOfRange(_))
+    ));
+    assert!(matches!(
+      checked_u16(Const::Signed(-1)),
+      Err(Error::OutOfRange(_))
+    ));
+    assert_eq!(checked_site(Const::Unsigned(40)).unwrap(), 40);
+    assert!(matches!(
+      checked_site(Const::Unsigned(41)),
+      Err(Error::BadSite(_))
+    ));
+    assert!(matches!(
+      checked_site(Const::Unsigned(256)),
+      Err(Error::BadSite(_))
+    ));
+    assert!(matches!(
+      checked_site(Const::Signed(-1)),
+      Err(Error::BadSite(_))
+    ));
+
+    let runtime = test_runtime(0, vec![Instruction::GetSite]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    cursor.op_stack.push(Const::Unsigned(256));
+    assert!(matches!(
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+      Err(Error::BadSite(_))
+    ));
+  }
+
+  #[test]
+  fn test_run_all_symmetries() {
+    // Copies the center atom one site to the west.
+    let runtime = test_runtime(
+      1,
+      vec![
+        Instruction::Push1,
+        Instruction::Push0,
+        Instruction::GetSite,
+        Instruction::SetSite,
+      ],
+    );
+    let mut window = [Const::Unsigned(0); 41];
+    window[0] = runtime.type_map[&1].new_atom();
+    let out = runtime.run_all_symmetries(&window, 1).unwrap();
+    for (k, o) in out.iter().enumerate() {
+      let s = Symmetries::from(1u8 << k);
+      for i in 0..41u8 {
+        assert_eq!(
+          o[mfm::map_site(i, s) as usize],
+          out[0][i as usize],
+          "{} {}",
+          s,
+          i
+        );
+      }
+    }
+    assert_eq!(out[0][1], window[0]);
+  }
+
+  #[test]
+  fn test_jump_relative_offset() {
+    let cases = [
+      (
+        Const::Unsigned(0),
+        vec![Const::Unsigned(1), Const::Unsigned(1)],
+      ),
+      (Const::Signed(-3), vec![Const::Unsigned(1)]),
+      (Const::Signed(-2), vec![]),
+      (Const::Unsigned(u128::MAX), vec![]),
+      (Const::Signed(i128::MAX), vec![]),
+      (Const::Signed(i128::MIN), vec![]),
+    ];
+    for (offset, want) in cases.iter() {
+      let runtime = test_runtime(
+        0,
+        vec![
+          Instruction::Jump(Arg::Runtime(3)),
+          Instruction::Push1,
+          Instruction::Exit,
+          Instruction::Push(*offset),
+          Instruction::JumpRelativeOffset,
+          Instruction::Push1,
+          Instruction::Jump(Arg::Runtime(1)),
+        ],
+      );
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert_eq!(&cursor.op_stack, want, "{}", offset);
+    }
+  }
+
+  #[test]
+  fn test_swap_sites_symmetry() {
+    // Swapping #0 and #1 moves the center atom one cell west under R000L, and one cell south
+    // under R090L, which maps (x, y) to (y, -x).
+    let runtime = test_runtime(
+      1,
+      vec![
+        Instruction::Push0,
+        Instruction::Push1,
+        Instruction::SwapSites,
+      ],
+    );
+    for (s, want) in [(Symmetries::R000L, (4, 0)), (Symmetries::R090L, (0, 1))].iter() {
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = mfm::DenseGrid::new(&mut rng, (5, 5)).unwrap();
+      ew.set_wrap(true);
+      let atom = runtime.type_map[&1].new_atom();
+      ew.set_cell(0, 0, atom);
+      let mut cursor = Cursor::with_symmetry(*s);
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert_eq!(ew.get_cell(want.0, want.1), atom, "{}", s);
+      assert_eq!(ew.population(), 1);
+    }
+  }
+
+  #[test]
+  fn test_runaway_limits() {
+    // Recurses forever.
+    let recurse = test_runtime(0, vec![Instruction::Call(Arg::Runtime(0))]);
+    // Pushes forever.
+    let push = test_runtime(
+      0,
+      vec![Instruction::Push1, Instruction::Jump(Arg::Runtime(0))],
+    );
+    for (runtime, overflow) in [(recurse, false), (push, true)].iter() {
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      cursor.set_limits(16, 8);
+      let res = Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map);
+      if *overflow {
+        assert!(matches!(res, Err(Error::StackOverflow)));
+        assert_eq!(cursor.op_stack.len(), 17);
+      } else {
+        assert!(matches!(res, Err(Error::CallDepthExceeded)));
+        assert_eq!(cursor.call_stack.len(), 8);
+      }
+    }
+  }
+
+  #[test]
+  fn test_site_is() {
+    let runtime = test_runtime(1, vec![Instruction::SiteIs(Arg::Runtime(2))]);
+    let mut other = Metadata::new();
+    other.type_num = 2;
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = mfm::DenseGrid::new(&mut rng, (3, 3)).unwrap();
+    ew.set_cell(0, 0, runtime.type_map[&1].new_atom());
+    ew.set_cell(1, 0, other.new_atom());
+    ew.set_cell(0, 1, runtime.type_map[&1].new_atom());
+    // Sites #4 (east) and #3 (south) hold type 2 and type 1, #2 (north) is off the grid and
+    // #1 (west) is too, though it would otherwise read as Empty.
+    for (site, want) in [(4u8, 1u8), (3, 0), (2, 0)].iter() {
+      let mut cursor = Cursor::new();
+      cursor.op_stack.push((*site).into());
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert_eq!(cursor.op_stack, vec![Const::from(*want)], "{}", site);
+    }
+    let runtime = test_runtime(1, vec![Instruction::SiteIs(Arg::Runtime(0))]);
+    let mut cursor = Cursor::new();
+    cursor.op_stack.push(1u8.into());
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(cursor.op_stack, vec![Const::from(0u8)]);
+  }
+
+  #[test]
+  fn test_is_empty() {
+    let runtime = test_runtime(1, vec![Instruction::IsEmpty]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = mfm::DenseGrid::new(&mut rng, (3, 3)).unwrap();
+    ew.set_cell(0, 0, runtime.type_map[&1].new_atom());
+    ew.set_cell(1, 0, runtime.type_map[&1].new_atom());
+    // Site #4 (east) is occupied, #3 (south) is empty and #1 (west) is off the grid.
+    for (site, want) in [(4u8, 0u8), (3, 1), (1, 0)].iter() {
+      let mut cursor = Cursor::new();
+      cursor.op_stack.push((*site).into());
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert_eq!(cursor.op_stack, vec![Const::from(*want)], "{}", site);
+    }
+    // Rotated half a turn, the empty south site #3 is read as the off-grid north site.
+    let mut cursor = Cursor::with_symmetry(Symmetries::R180L);
+    cursor.op_stack.push(3u8.into());
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(cursor.op_stack, vec![Const::from(0u8)]);
+  }
+
+  #[test]
+  fn test_transactions() {
+    let code = vec![
+      Instruction::Begin,
+      Instruction::Push1,
+      Instruction::Push0,
+      Instruction::SetSite,
+      Instruction::Push2,
+      Instruction::Push0,
+      Instruction::SetSite,
+      Instruction::Push1,
+      Instruction::Push2,
+      Instruction::SwapSites,
+      Instruction::Abort,
+    ];
+    let runtime = test_runtime(1, code.clone());
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    let atom = runtime.type_map[&1].new_atom();
+    ew.set(0, atom);
+    ew.set(2, atom);
+    let mut cursor = Cursor::new();
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(ew.get(1), Const::from(0u8));
+    assert_eq!(ew.get(2), atom);
+
+    let mut code = code;
+    *code.last_mut().unwrap() = Instruction::Commit;
+    let runtime = test_runtime(1, code);
+    let mut cursor = Cursor::new();
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(ew.get(1), Const::from(0u8));
+    assert_eq!(ew.get(2), Const::from(0u8));
+
+    // A transaction left open at the end of the event is discarded.
+    let runtime = test_runtime(
+      1,
+      vec![
+        Instruction::Begin,
+        Instruction::Push0,
+        Instruction::Push0,
+        Instruction::SetSite,
+      ],
+    );
+    let mut cursor = Cursor::new();
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(ew.get(0), atom);
+
+    let runtime = test_runtime(1, vec![Instruction::Begin, Instruction::Begin]);
+    let mut cursor = Cursor::new();
+    assert!(matches!(
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+      Err(Error::NestedTransaction)
+    ));
+    let runtime = test_runtime(1, vec![Instruction::Commit]);
+    let mut cursor = Cursor::new();
+    assert!(matches!(
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+      Err(Error::NoTransaction)
+    ));
+  }
+
+  #[test]
+  fn test_site_out_of_radius() {
+    let runtime = test_runtime(1, vec![Instruction::GetSite]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    cursor.op_stack.push(4u8.into());
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    let mut cursor = Cursor::new();
+    cursor.op_stack.push(40u8.into());
+    assert!(matches!(
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+      Err(Error::SiteOutOfRadius(40))
+    ));
+  }
+
+  #[test]
+  fn test_site_to_xy() {
+    let runtime = test_runtime(0, vec![Instruction::SiteToXY]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    cursor.op_stack.push(8u8.into());
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert!(matches!(
+      cursor.op_stack[..],
+      [Const::Signed(1), Const::Signed(1)]
+    ));
+
+    let runtime = test_runtime(0, vec![Instruction::XYToSite]);
+    let mut cursor = Cursor::new();
+    cursor.op_stack.push(Const::Signed(1));
+    cursor.op_stack.push(Const::Signed(1));
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(cursor.op_stack, vec![Const::from(8u8)]);
+
+    let mut cursor = Cursor::new();
+    cursor.op_stack.push(Const::Signed(-4));
+    cursor.op_stack.push(Const::Signed(1));
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert!(matches!(cursor.op_stack[..], [Const::Signed(-1)]));
+  }
+
+  #[test]
+  fn test_divide_by_zero() {
+    for op in [Instruction::Div, Instruction::Mod, Instruction::ModEuclid].iter() {
+      let runtime = test_runtime(0, vec![Instruction::Push1, Instruction::Push0, *op]);
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      assert!(
+        matches!(
+          Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+          Err(Error::DivideByZero(2))
+        ),
+        "{}",
+        op
+      );
+    }
+  }
+
+  #[test]
+  fn test_divide_overflow() {
+    for (op, want) in [
+      (Instruction::Div, i128::MAX),
+      (Instruction::Mod, 0),
+      (Instruction::ModEuclid, 0),
+    ]
+    .iter()
+    {
+      let runtime = test_runtime(
+        0,
+        vec![
+          Instruction::Push(Const::Signed(i128::MIN)),
+          Instruction::Push(Const::Signed(-1)),
+          *op,
+        ],
+      );
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert_eq!(cursor.op_stack, vec![Const::Signed(*want)], "{}", op);
+    }
+  }
+
+  #[test]
+  fn test_site_paint() {
+    let runtime = test_runtime(
+      1,
+      vec![
+        Instruction::Push3,
+        Instruction::Push(0x11223344u32.into()),
+        Instruction::SetSitePaint,
+        Instruction::Push3,
+        Instruction::GetSitePaint,
+      ],
+    );
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(ew.get_paint_at(3).bits(), 0x11223344);
+    assert_eq!(ew.get_paint().bits(), 0);
+    assert_eq!(cursor.op_stack, vec![Const::from(0x11223344u32)]);
+  }
+
+  #[test]
+  fn test_no_progress() {
+    let runtime = test_runtime(
+      0,
+      vec![Instruction::Nop, Instruction::Jump(Arg::Runtime(1))],
+    );
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    cursor.set_loop_limit(Some(3));
+    assert!(matches!(
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+      Err(Error::NoProgress(1))
+    ));
+
+    // A counting loop changes the stack on every pass.
+    let runtime = test_runtime(
+      0,
+      vec![
+        Instruction::Push0,
+        Instruction::Push1,
+        Instruction::Add,
+        Instruction::Dup,
+        Instruction::Push10,
+        Instruction::Less,
+        Instruction::JumpNonZero(Arg::Runtime(1)),
+      ],
+    );
+    let mut cursor = Cursor::new();
+    cursor.set_loop_limit(Some(1));
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+  }
+
+  #[test]
+  fn test_cursor_serialize() {
+    let prefix = vec![
+      Instruction::Push1,
+      Instruction::Push(Const::Signed(-7)),
+      Instruction::SaveSymmetries,
+      Instruction::UseSymmetries(Symmetries::R090L),
+    ];
+    let mut full = prefix.clone();
+    full.extend(vec![
+      Instruction::Add,
+      Instruction::Push2,
+      Instruction::Mul,
+      Instruction::RestoreSymmetries,
+    ]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+
+    let runtime = test_runtime(0, prefix.clone());
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut paused = Cursor::new();
+    Runtime::execute(&mut ew, &mut paused, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(paused.ip, prefix.len());
+
+    let b = paused.serialize();
+    let mut resumed = Cursor::deserialize(&b).unwrap();
+    assert_eq!(resumed.serialize(), b);
+    let runtime = test_runtime(0, full);
+    Runtime::execute(&mut ew, &mut resumed, &runtime.code_map, &runtime.type_map).unwrap();
+
+    let mut fresh = Cursor::new();
+    Runtime::execute(&mut ew, &mut fresh, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(resumed.op_stack, fresh.op_stack);
+    assert_eq!(resumed.op_stack, vec![Const::Signed(-12)]);
+    assert_eq!(resumed.symmetry, fresh.symmetry);
+
+    assert!(matches!(
+      Cursor::deserialize(&b[..b.len() - 1]),
+      Err(Error::IOError(_))
+    ));
+    let mut long = b.clone();
+    long.push(0);
+    assert!(matches!(
+      Cursor::deserialize(&long),
+      Err(Error::TrailingCursorData(1))
+    ));
+  }
+
+  #[test]
+  fn test_exited() {
+    let runtime = test_runtime(0, vec![Instruction::Exit]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert!(cursor.exited());
+    cursor.reset(Symmetries::R000L);
+    assert!(!cursor.exited());
+    let runtime = test_runtime(0, vec![Instruction::Nop]);
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert!(!cursor.exited());
+  }
+
+  #[test]
+  fn test_stack_underflow() {
+    let runtime = test_runtime(0, vec![Instruction::Push1, Instruction::Add]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    assert!(matches!(
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+      Err(Error::StackUnderflow { ip: 1, op: "add" })
+    ));
+    for op in &[
+      Instruction::Pop,
+      Instruction::Over,
+      Instruction::Swap,
+      Instruction::Rot,
+      Instruction::Ret,
+      Instruction::RestoreSymmetries,
+    ] {
+      let runtime = test_runtime(0, vec![*op]);
+      let mut cursor = Cursor::new();
+      assert!(
+        matches!(
+          Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+          Err(Error::StackUnderflow { ip: 0, op: m }) if m == op.mnemonic()
+        ),
+        "{}",
+        op
+      );
+    }
+    let runtime = test_runtime(
+      0,
+      vec![Instruction::Push1, Instruction::Push2, Instruction::Rot],
+    );
+    let mut cursor = Cursor::new();
+    assert!(matches!(
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+      Err(Error::StackUnderflow { ip: 2, op: "rot" })
+    ));
+  }
+
+  #[test]
+  fn test_neighbor_count() {
+    let runtime = test_runtime(1, vec![Instruction::NeighborCount(Arg::Runtime(2))]);
+    let mut other = Metadata::new();
+    other.type_num = 2;
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    ew.set(1, other.new_atom());
+    ew.set(2, runtime.type_map[&1].new_atom());
+    ew.set(4, other.new_atom());
+    ew.set(5, other.new_atom());
+    let mut cursor = Cursor::with_symmetry(Symmetries::R090L);
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(cursor.op_stack, vec![Const::Unsigned(2)]);
+  }
+
+  #[test]
+  fn test_count_type() {
+    let runtime = test_runtime(1, vec![Instruction::Push2, Instruction::CountType]);
+    let mut other = Metadata::new();
+    other.type_num = 2;
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    ew.set(1, other.new_atom());
+    ew.set(2, runtime.type_map[&1].new_atom());
+    ew.set(4, other.new_atom());
+    ew.set(5, other.new_atom());
+    ew.set(12, other.new_atom());
+    // Sites 5 and 12 lie outside radius 1.
+    for symmetry in &[Symmetries::R000L, Symmetries::R090L, Symmetries::R180R] {
+      let mut cursor = Cursor::with_symmetry(*symmetry);
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert_eq!(cursor.op_stack, vec![Const::Unsigned(2)]);
+    }
+    let runtime = test_runtime(1, vec![Instruction::Push1, Instruction::CountType]);
+    let mut cursor = Cursor::new();
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert_eq!(cursor.op_stack, vec![Const::Unsigned(1)]);
+  }
+
+  #[test]
+  fn test_rand_range() {
+    let runtime = test_runtime(0, vec![Instruction::Push6, Instruction::RandRange]);
+    let mut counts = [0; 6];
+    for seed in 0..6000 {
+      let mut rng = SmallRng::seed_from_u64(seed);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      let x: u32 = cursor.pop().unwrap().into();
+      counts[x as usize] += 1;
+    }
+    assert!(counts.iter().all(|&c| c > 850 && c < 1150), "{:?}", counts);
+
+    let runtime = test_runtime(0, vec![Instruction::Push0, Instruction::RandRange]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    assert!(cursor.pop().unwrap().is_zero());
+
+    let runtime = test_runtime(
+      0,
+      vec![Instruction::Push(Const::Signed(-1)), Instruction::RandRange],
+    );
+    let mut cursor = Cursor::new();
+    assert!(matches!(
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map),
+      Err(Error::OutOfRange(Const::Signed(-1)))
+    ));
+  }
+
+  #[test]
+  fn test_bitscan_zero() {
+    for op in &[Instruction::BitScanForward, Instruction::BitScanReverse] {
+      let runtime = test_runtime(0, vec![Instruction::Push0, *op]);
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert_eq!(cursor.op_stack, vec![Const::from(Const::NO_BIT)]);
+    }
+  }
+
+  #[test]
+  fn test_rand_below_wide() {
+    let mut rng = SmallRng::seed_from_u64(1548);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    // Bounds past 32 bits are used in full, not truncated.
+    for &n in &[1u128 << 32, (1 << 40) + 3, 1 << 96, u128::MAX] {
+      let mut high = false;
+      for _ in 0..200 {
+        let x = rand_below(&mut ew, n);
+        assert!(x < n, "{} >= {}", x, n);
+        high |= x >= n / 2;
+      }
+      assert!(high, "never drew from the upper half of {}", n);
+    }
+  }
+
+  #[test]
+  fn test_for_each_symmetry() {
+    let code = ast::for_each_symmetry(
+      Symmetries::all(),
+      vec![
+        Node::Instruction(Instruction::Push1),
+        Node::Instruction(Instruction::GetSite),
+      ],
+    );
+    let code = code
+      .into_iter()
+      .map(|n| match n {
+        Node::Instruction(i) => i,
+        _ => unreachable!(),
+      })
+      .collect();
+    let runtime = test_runtime(1, code);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    for i in 1..5 {
+      ew.set(i, (i as u32).into());
+    }
+    let mut cursor = Cursor::new();
+    Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+    let want: Vec<Const> = (0..8)
+      .map(|i| (mfm::map_site(1, (1u8 << i).into()) as u32).into())
+      .collect();
+    assert_eq!(cursor.op_stack, want);
+    assert_eq!(cursor.symmetry, Symmetries::R000L);
+  }
+
+  #[test]
+  fn test_atom_equal() {
+    let mut a = Const::Unsigned(0);
+    a.store(1u8.into(), &FieldSelector::TYPE);
+    a.store(42u8.into(), &FieldSelector::DATA);
+    let mut b = a;
+    b.store(0x1ffu16.into(), &FieldSelector::CHECKSUM);
+    for (op, want) in &[(Instruction::AtomEqual, 1u8), (Instruction::Equal, 0u8)] {
+      let runtime = test_runtime(0, vec![*op]);
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      cursor.op_stack.push(a);
+      cursor.op_stack.push(b);
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert_eq!(cursor.op_stack, vec![Const::from(*want)]);
+    }
+  }
+
+  #[test]
+  fn test_compare() {
+    let runtime = test_runtime(0, vec![Instruction::Compare]);
+    let cases = [
+      (Const::Unsigned(1), Const::Unsigned(2), -1),
+      (Const::Unsigned(2), Const::Unsigned(2), 0),
+      (Const::Unsigned(3), Const::Unsigned(2), 1),
+      (Const::Signed(-3), Const::Signed(-2), -1),
+      (Const::Signed(-2), Const::Signed(-2), 0),
+      (Const::Signed(2), Const::Signed(-2), 1),
+      (Const::Signed(-1), Const::Unsigned(0), -1),
+      (Const::Signed(2), Const::Unsigned(2), 0),
+      (Const::Unsigned(0), Const::Signed(-1), 1),
+    ];
+    for (a, b, want) in cases.iter() {
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      cursor.op_stack.push(*a);
+      cursor.op_stack.push(*b);
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert!(matches!(cursor.op_stack[..], [Const::Signed(x)] if x == *want));
+    }
+  }
+
+  #[test]
+  fn test_wrapping_mode() {
+    let mut runtime = test_runtime(0, vec![Instruction::Add]);
+    for (wrapping, want) in [(false, u128::MAX), (true, 0)].iter() {
+      runtime.type_map.get_mut(&1).unwrap().wrapping = *wrapping;
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      cursor.op_stack.push(Const::Unsigned(u128::MAX));
+      cursor.op_stack.push(Const::Unsigned(1));
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert!(matches!(cursor.op_stack[..], [Const::Unsigned(x)] if x == *want));
+    }
+  }
+
+  #[test]
+  fn test_signed_field_round_trip() {
+    let f = FieldSelector {
+      offset: 3,
+      length: 5,
+      data_type: DataType::Unsigned,
+    };
+    let runtime = test_runtime(
+      0,
+      vec![
+        Instruction::SetSignedField(Arg::Runtime(f)),
+        Instruction::GetSignedField(Arg::Runtime(f)),
+      ],
+    );
+    for x in &[-1, -3, -16, 0, 15] {
+      let mut rng = StepRng::new(0, 1);
+      let mut ew = MinimalEventWindow::new(&mut rng);
+      ew.set(0, runtime.type_map[&1].new_atom());
+      let mut cursor = Cursor::new();
+      cursor.op_stack.push(Const::Unsigned(u128::MAX));
+      cursor.op_stack.push(Const::Signed(*x));
+      Runtime::execute(&mut ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+      assert!(matches!(cursor.op_stack[..], [Const::Signed(y)] if y == *x));
+    }
+  }
+
+  #[test]
+  fn test_physics_hash() {
+    let a = element_bytes("a", 1, 1, &[18]);
+    let b = element_bytes("a", 2, 1, &[19]);
+    let mut x = Runtime::new();
+    x.load_from_reader(&mut a.as_slice()).unwrap();
+    x.load_from_reader(&mut b.as_slice()).unwrap();
+    let mut y = Runtime::new();
+    y.load_from_reader(&mut b.as_slice()).unwrap();
+    y.load_from_reader(&mut a.as_slice()).unwrap();
+    assert_eq!(x.physics_hash(), y.physics_hash());
+    y.load_from_reader(&mut element_bytes("a", 2, 1, &[20]).as_slice())
+      .unwrap();
+    assert_ne!(x.physics_hash(), y.physics_hash());
+  }
+
+  #[test]
+  fn test_run_progress() {
+    let runtime = test_runtime(0, vec![Instruction::Nop]);
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    let mut calls = Vec::new();
+    let stats = runtime.run(&mut ew, &mut cursor, 10, 3, 0, |_, p| {
+      calls.push((p.events, p.total, p.population))
+    });
+    assert_eq!(stats.events, 10);
+    assert!(!stats.quiescent);
+    assert_eq!(calls, vec![(3, 10, 1), (6, 10, 1), (9, 10, 1)]);
+  }
+
+  #[test]
+  fn test_run_quiescence() {
+    // Writes the center atom onto itself, which changes nothing.
+    let runtime = test_runtime(
+      0,
+      vec![
+        Instruction::Push0,
+        Instruction::Push0,
+        Instruction::GetSite,
+        Instruction::SetSite,
+      ],
+    );
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    let run = |ew: &mut MinimalEventWindow<_>, cursor: &mut Cursor, quiescence| {
+      runtime
+        .run(ew, cursor, 1000, 0, quiescence, |_, _| {})
+        .events
+    };
+    assert_eq!(run(&mut ew, &mut cursor, 5), 5);
+    assert_eq!(run(&mut ew, &mut cursor, 0), 1000);
+
+    // Paint changes on every event keep the run going.
+    let runtime = test_runtime(0, vec![Instruction::Rand, Instruction::SetPaint]);
+    assert_eq!(
+      runtime
+        .run(&mut ew, &mut cursor, 100, 0, 5, |_, _| {})
+        .events,
+      100
+    );
+
+    // Also when one such atom is alone on a large grid, where most events change nothing.
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut grid = mfm::DenseGrid::new(&mut rng, (20, 20)).unwrap();
+    grid.set_absolute(0, runtime.type_map[&1].new_atom());
+    grid.reset();
+    let stats = runtime.run(&mut grid, &mut cursor, 2000, 0, 5, |_, _| {});
+    assert!(!stats.quiescent);
+    assert_eq!(stats.events, 2000);
+  }
+
+  #[test]
+  fn test_reset_cursor() {
+    let mut runtime = test_runtime(0, vec![]);
+    runtime.type_map.get_mut(&1).unwrap().symmetries = Symmetries::R090L | Symmetries::R270L;
+    let mut other = Metadata::new();
+    other.type_num = 2;
+    other.symmetries = Symmetries::R000R | Symmetries::R180R;
+    runtime.type_map.insert(2, other.clone());
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    let mut cursor = Cursor::new();
+    for _ in 0..4 {
+      ew.set(0, runtime.type_map[&1].new_atom());
+      runtime.reset_cursor(&mut ew, &mut cursor);
+      assert!((Symmetries::R090L | Symmetries::R270L).contains(cursor.symmetry));
+      ew.set(0, other.new_atom());
+      runtime.reset_cursor(&mut ew, &mut cursor);
+      assert!((Symmetries::R000R | Symmetries::R180R).contains(cursor.symmetry));
+    }
+    ew.set(0, Const::from(0u8));
+    runtime.reset_cursor(&mut ew, &mut cursor);
+    assert_eq!(cursor.symmetry, Symmetries::R000L);
+  }
+
+  #[test]
+  fn test_run_stats() {
+    let runtime = test_runtime(
+      1,
+      vec![
+        Instruction::Push1,
+        Instruction::Push0,
+        Instruction::GetSite,
+        Instruction::SetSite,
+        Instruction::Push1,
+        Instruction::Push2,
+        Instruction::SwapSites,
+      ],
+    );
+    let mut rng = StepRng::new(0, 1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(0, runtime.type_map[&1].new_atom());
+    let mut cursor = Cursor::new();
+    let stats = runtime.run(&mut ew, &mut cursor, 3, 0, 0, |_, _| {});
+    assert_eq!((stats.events, stats.sets, stats.swaps), (3, 3, 3));
+    assert!(stats.error.is_none());
+
+    let mut unknown = Metadata::new();
+    unknown.type_num = 5;
+    ew.set(0, unknown.new_atom());
+    let stats = runtime.run(&mut ew, &mut cursor, 3, 0, 0, |_, _| {});
+    assert_eq!(stats.events, 0);
+    assert!(matches!(stats.error, Some(Error::UnknownElement(5))));
+  }
+
+  #[test]
+  fn test_check_stack_discipline() {
+    let mut runtime = Runtime::new();
+    runtime.check_stack = true;
+    // push1 jumpzero 4 push1 add; add is reachable with a single value by falling through.
+    assert!(matches!(
+      runtime.load_from_reader(&mut element_bytes("a", 1, 4, &[18, 86, 0, 4, 18, 67]).as_slice()),
+      Err(Error::StackDiscipline(3))
+    ));
+    // push1 push1 jumpzero 4 push1 add; add is reached with one value only along the shorter path.
+    runtime
+      .load_from_reader(&mut element_bytes("a", 1, 5, &[18, 18, 86, 0, 4, 18, 67]).as_slice())
+      .unwrap();
+    // push1 push1 add
+    runtime
+      .load_from_reader(&mut element_bytes("a", 1, 3, &[18, 18, 67]).as_slice())
+      .unwrap();
+    // push2 jumprelativeoffset push1 pop pop; the jump skips the push.
+    assert!(matches!(
+      runtime.load_from_reader(&mut element_bytes("a", 1, 5, &[19, 85, 18, 59, 59]).as_slice()),
+      Err(Error::StackDiscipline(3))
+    ));
+    // rand jumprelativeoffset push1; the jump could go anywhere.
+    assert!(matches!(
+      runtime.load_from_reader(&mut element_bytes("a", 1, 3, &[90, 85, 18]).as_slice()),
+      Err(Error::StackDiscipline(1))
+    ));
+    // push1 push1 push1 lessequal push2 mul jumprelativeoffset pop; a jump over the pop or not.
+    runtime
+      .load_from_reader(&mut element_bytes("a", 1, 8, &[18, 18, 18, 74, 19, 71, 85, 59]).as_slice())
+      .unwrap();
+    runtime.check_stack = false;
+    runtime
+      .load_from_reader(&mut element_bytes("a", 1, 1, &[67]).as_slice())
+      .unwrap();
+  }
+}
@@ -0,0 +1,88 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Compiles `tests/testdata/<name>.s` with ewac and returns the path of the compiled element.
+fn compile(name: &str) -> PathBuf {
+    compile_path(
+        &PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/testdata")
+            .join(format!("{}.s", name)),
+    )
+}
+
+/// Compiles the source file at `src` with ewac and returns the path of the compiled element.
+fn compile_path(src: &Path) -> PathBuf {
+    let name = src.file_stem().unwrap();
+    let out = env::temp_dir().join(format!("ewar-test-{}", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_ewac"))
+        .arg("-o")
+        .arg(&out)
+        .arg(src)
+        .status()
+        .expect("failed to run ewac");
+    assert!(status.success());
+    out.join(name)
+}
+
+fn ewar(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ewar"))
+        .args(args)
+        .output()
+        .expect("failed to run ewar")
+}
+
+#[test]
+fn test_examples() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut dirs = vec![root];
+    let mut n = 0;
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if path.extension().is_none_or(|e| e != "s") {
+                continue;
+            }
+            let bin = compile_path(&path);
+            let out = ewar(&["-q", bin.to_str().unwrap()]);
+            let stdout = String::from_utf8_lossy(&out.stdout);
+            assert!(out.status.success(), "{}: {}", path.display(), stdout);
+            assert!(
+                stdout.contains("errors: 0\n"),
+                "{}: {}",
+                path.display(),
+                stdout
+            );
+            fs::remove_file(bin).ok();
+            n += 1;
+        }
+    }
+    assert!(n > 0);
+}
+
+#[test]
+fn test_erase_trials() {
+    let path = compile("erase");
+    let path = path.to_str().unwrap();
+    // 41 empty sites of 16 bytes each.
+    let empty = format!("{}=", "A".repeat(875));
+
+    let out = ewar(&[path, "-n", "3", "--test", &empty]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.contains("trials: 3\n"), "{}", stdout);
+    assert!(stdout.contains("exited: 3\n"), "{}", stdout);
+    assert!(stdout.contains("errors: 0\n"), "{}", stdout);
+    assert!(stdout.contains("PASS"), "{}", stdout);
+
+    let out = ewar(&[path, "--test", "AAAA"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("FAIL"));
+
+    fs::remove_file(path).ok();
+}
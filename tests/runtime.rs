@@ -0,0 +1,325 @@
+#[path = "../src/runtime/mod.rs"]
+mod runtime;
+
+#[path = "../src/base/mod.rs"]
+mod base;
+
+#[path = "../src/ast.rs"]
+mod ast;
+
+#[path = "../src/code.rs"]
+mod code;
+
+use crate::ast::{Arg, Instruction};
+use crate::base::arith::Const;
+use crate::base::{DataType, FieldSelector};
+use crate::code::{Compiler, Includes};
+use crate::runtime::mfm::{EventWindow, Metadata, MinimalEventWindow};
+use crate::runtime::{Cursor, Error, Runtime};
+use rand::rngs::SmallRng;
+use rand::{RngCore, SeedableRng};
+
+/// Compiles `src` with `compiler` and returns the compiled elements.
+fn compile_with(mut compiler: Compiler, src: &str) -> Vec<u8> {
+    let mut bin = Vec::new();
+    compiler
+        .compile_to_writer(&mut bin, src, &mut Includes::new())
+        .unwrap();
+    bin
+}
+
+fn compile(src: &str) -> Vec<u8> {
+    compile_with(Compiler::new("test"), src)
+}
+
+/// Loads the compiled elements in `bin` into a new runtime and returns them in file order.
+fn load(bin: &[u8]) -> (Runtime<'static>, Vec<Metadata>) {
+    let mut runtime = Runtime::new();
+    let elems = runtime.load_all_from_reader(&mut &bin[..]).unwrap();
+    (runtime, elems)
+}
+
+/// Places `elem` at the origin of `ew` and runs one event of it to the end.
+fn execute<R: RngCore>(runtime: &Runtime, ew: &mut MinimalEventWindow<R>, elem: &Metadata) {
+    ew.set(0, elem.new_atom());
+    let mut cursor = Cursor::new();
+    Runtime::execute(ew, &mut cursor, &runtime.code_map, &runtime.type_map).unwrap();
+}
+
+#[test]
+fn test_multi_element_file() {
+    let (runtime, elems) = load(&compile(
+        ".symbol \"a\"\n.name \"A\"\n  gettype \"B\"\n.name \"B\"\n.symbol \"b\"\n  push1\n",
+    ));
+    let names: Vec<_> = elems
+        .iter()
+        .map(|e| (e.name.as_str(), e.symbol.as_str(), e.type_num))
+        .collect();
+    assert_eq!(names, vec![("A", "a", 1), ("B", "b", 2)]);
+    assert_eq!(runtime.code_map[&1].len(), 1);
+    assert!(matches!(
+        runtime.code_map[&1][0],
+        Instruction::GetType(Arg::Runtime(2))
+    ));
+}
+
+#[test]
+fn test_extends() {
+    let (runtime, elems) = load(&compile(
+        r#"
+.name "Base"
+.parameter step 2
+  jump main
+inc:
+  getparameter step
+  add
+  ret
+main:
+.name "Derived"
+.extends "Base"
+.radius 1
+  push1
+  push1
+  call inc
+  setsite
+"#,
+    ));
+    let derived = &elems[1];
+    assert!(derived.parameter("step").is_some());
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    execute(&runtime, &mut ew, derived);
+    assert_eq!(ew.get(1), Const::Unsigned(3));
+    assert_eq!(runtime.code_map[&derived.type_num].len(), 8);
+}
+
+#[test]
+fn test_code_index() {
+    let (runtime, elems) = load(&compile(
+        ".name \"A\"\n  jump end\nloop:\n  nop\n  jump loop\nend:\n  push1\n",
+    ));
+    let t = elems[0].type_num;
+    assert_eq!(runtime.label_offset(t, "loop"), Some(1));
+    assert_eq!(runtime.label_offset(t, "end"), Some(3));
+    assert_eq!(runtime.label_offset(t, "start"), None);
+    assert_eq!(runtime.code_index_map[&t].len(), 2);
+}
+
+#[test]
+fn test_get_parameter_of() {
+    let (runtime, elems) = load(&compile(
+        r#"
+.name "A"
+.radius 1
+.parameter speed 3
+  push1
+  gettype "B"
+  getparamof speed
+  setsite
+.name "B"
+.radius 1
+.parameter speed 5
+  push1
+  gettype "A"
+  getparamof speed
+  setsite
+  push2
+  gettype "A"
+  getparamof missing
+  setsite
+"#,
+    ));
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    ew.set(2, Const::Unsigned(9));
+    for (elem, want) in elems.iter().zip(&[5u8, 3]) {
+        execute(&runtime, &mut ew, elem);
+        assert_eq!(ew.get(1), Const::from(*want));
+    }
+    assert!(ew.get(2).is_zero());
+}
+
+#[test]
+fn test_const_widths() {
+    let consts = [
+        Const::Unsigned(0xff),
+        Const::Unsigned(0x1234),
+        Const::Unsigned(0x1234_5678),
+        Const::Unsigned(0x12_3456_789a),
+        Const::Signed(-1),
+        Const::Signed(-300),
+        Const::Signed(-70000),
+        Const::Signed(-0x12_3456_789a),
+    ];
+    let mut src = ".name \"A\"\n".to_owned();
+    for c in consts.iter() {
+        src.push_str(&format!("  push {}\n", c));
+    }
+    let bin = compile(&src);
+    let (runtime, elems) = load(&bin);
+    let code = &runtime.code_map[&elems[0].type_num];
+    for (c, instr) in consts.iter().zip(code.iter()) {
+        match (c, instr) {
+            (Const::Unsigned(x), Instruction::Push(Const::Unsigned(y))) => assert_eq!(x, y),
+            (Const::Signed(x), Instruction::Push(Const::Signed(y))) => assert_eq!(x, y),
+            x => panic!("unexpected: {:?}", x),
+        }
+    }
+    // Each push costs its opcode and type byte, then 1 + 2 + 4 + 12 + 1 + 2 + 4 + 12 bytes.
+    assert_eq!(bin.len() - compile(".name \"A\"\n").len(), 8 * 2 + 38);
+}
+
+#[test]
+fn test_strip_docs() {
+    let src = r#"
+.name "A"
+.desc "Copies itself west."
+.author "A. Author"
+.license "MIT"
+.radius 1
+  push1
+  push0
+  getsite
+  setsite
+"#;
+    let run = |strip_docs| {
+        let mut compiler = Compiler::new("test");
+        compiler.strip_docs = strip_docs;
+        let bin = compile_with(compiler, src);
+        let (runtime, mut elems) = load(&bin);
+        let elem = elems.remove(0);
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut ew = MinimalEventWindow::new(&mut rng);
+        execute(&runtime, &mut ew, &elem);
+        let sites: Vec<Const> = (0..41).map(|i| ew.get(i)).collect();
+        (bin.len(), elem, sites)
+    };
+    let (full_len, full, full_sites) = run(false);
+    let (stripped_len, stripped, stripped_sites) = run(true);
+    assert!(stripped_len < full_len);
+    assert_eq!(full.descs, vec!["Copies itself west.".to_owned()]);
+    assert!(stripped.descs.is_empty() && stripped.authors.is_empty());
+    assert_eq!(stripped.name, "A");
+    assert_eq!(stripped.radius, 1);
+    assert_eq!(full_sites, stripped_sites);
+}
+
+#[test]
+fn test_for_each_site() {
+    let (runtime, elems) = load(&compile(
+        r#"
+.name "Sum"
+.radius 1
+  push0
+  foreachsite {
+    getsitefield data
+    rot
+    rot
+    add
+    swap
+  }
+  push0
+  swap
+  setsite
+.name "First"
+.radius 2
+  push0
+  foreachsite {
+    isempty
+    jumpnonzero next
+    swap
+    pop
+    dup
+    break
+  next:
+  }
+  push0
+  swap
+  setsite
+"#,
+    ));
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+
+    // Site 5 lies outside the radius of Sum.
+    for i in 1..6 {
+        let mut a = Const::Unsigned(0);
+        a.store(Const::from(i as u8 * 10), &FieldSelector::DATA);
+        ew.set(i, a);
+    }
+    execute(&runtime, &mut ew, &elems[0]);
+    assert_eq!(ew.get(0), Const::from(100u8));
+
+    ew.set(7, elems[0].new_atom());
+    ew.set(9, elems[0].new_atom());
+    execute(&runtime, &mut ew, &elems[1]);
+    assert_eq!(ew.get(0), Const::from(7u16));
+}
+
+#[test]
+fn test_signed_field() {
+    let (runtime, elems) = load(&compile(
+        r#"
+.name "A"
+.radius 1
+.field delta,4,4,signed
+.field count,0,4
+.parameter delta -3
+  push1
+  push0
+  getsitefield delta
+  setsite
+"#,
+    ));
+    let elem = &elems[0];
+    assert_eq!(
+        elem.field("delta").map(|f| f.data_type),
+        Some(DataType::Signed)
+    );
+    assert_eq!(
+        elem.field("count").map(|f| f.data_type),
+        Some(DataType::Unsigned)
+    );
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    execute(&runtime, &mut ew, elem);
+    assert_eq!(ew.get(1), Const::Signed(-3));
+
+    let mut v = Vec::new();
+    assert!(Compiler::new("test")
+        .compile_to_writer(
+            &mut v,
+            ".name \"A\"\n.field x,120,9\n",
+            &mut Includes::new()
+        )
+        .is_err());
+}
+
+#[test]
+fn test_merge() {
+    let (mut runtime, _) = load(&compile(".name \"A\"\n.name \"B\"\n"));
+    let other = compile(
+        ".name \"Dst\"\n.name \"Src\"\n.radius 1\n  push1\n  push0\n  gettype \"Dst\"\n  setfield type\n  setsite\n",
+    );
+    let mapping = runtime.merge(&other, 10).unwrap();
+    assert_eq!(mapping, [(1, 10), (2, 11)].iter().copied().collect());
+    assert_eq!(runtime.type_map[&1].name, "A");
+    assert_eq!(runtime.type_map[&2].name, "B");
+    assert_eq!(runtime.type_map[&10].name, "Dst");
+    assert_eq!(runtime.type_map[&11].name, "Src");
+    assert_eq!(runtime.type_map[&11].type_num, 11);
+
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut ew = MinimalEventWindow::new(&mut rng);
+    let src = runtime.type_map[&11].clone();
+    execute(&runtime, &mut ew, &src);
+    assert_eq!(ew.get(1).apply(&FieldSelector::TYPE), Const::Unsigned(10));
+
+    // The last type number can be assigned, but not one past it.
+    let mapping = runtime.merge(&compile(".name \"C\"\n"), 65535).unwrap();
+    assert_eq!(mapping, [(1, 65535)].iter().copied().collect());
+    assert!(matches!(
+        runtime.merge(&other, 65535),
+        Err(Error::TypeNumbersExhausted)
+    ));
+}
@@ -0,0 +1,7 @@
+.name "Erase"
+.radius 1
+
+    push0
+    push0
+    setsite
+    exit